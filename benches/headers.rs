@@ -0,0 +1,103 @@
+//! Throughput of the abundance lookup in FASTA headers: a regular expression compiled for each
+//! record on a freshly formatted header (behaviour of versions <= 0.2.0), compiled once, and the
+//! byte-level parser of the presets, alone and while computing a spectrum
+
+use bio::io::fasta;
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use regex::Regex;
+
+use logan_kmer_spectrum::{HeaderAbundance, HeaderField, HeaderFormat, Record, SpectrumBuilder};
+
+/// Number of records of each benchmark
+const RECORDS: usize = 100_000;
+
+/// Number of records of the benchmark compiling a regular expression for each record
+const SLOW_RECORDS: usize = 1_000;
+
+/// Logan-style unitig identifiers and descriptions
+fn headers() -> Vec<(String, String)> {
+    (0..RECORDS)
+        .map(|i| {
+            let abundance = (i * 7919) % 1000;
+            (
+                format!("SRR1_{}", i),
+                format!("ka:f:{}.{:03} L:+:{}:+", abundance, i % 1000, i + 1),
+            )
+        })
+        .collect()
+}
+
+/// Abundance of a header, if any
+fn found(abundance: HeaderAbundance) -> Option<f64> {
+    match abundance {
+        HeaderAbundance::Found(abundance) => Some(abundance),
+        _ => None,
+    }
+}
+
+fn header_parsing(c: &mut Criterion) {
+    let headers = headers();
+    let mut group = c.benchmark_group("headers");
+
+    // Compiling a regular expression per record is so slow that it runs on fewer records
+    group.throughput(Throughput::Elements(SLOW_RECORDS as u64));
+    group.bench_function("regex per record", |b| {
+        b.iter(|| {
+            headers[..SLOW_RECORDS]
+                .iter()
+                .filter_map(|(id, desc)| {
+                    let header = format!("{} {}", id, desc);
+                    let regex = Regex::new(r"ka:f:(\d+)").unwrap();
+                    let value = regex.captures(&header)?.get(1)?;
+                    value.as_str().parse::<f64>().ok()
+                })
+                .sum::<f64>()
+        })
+    });
+
+    group.throughput(Throughput::Elements(RECORDS as u64));
+    let regex = HeaderField::from_regex(r"ka:f:(\d+(?:\.\d+)?)").unwrap();
+    group.bench_function("regex compiled once", |b| {
+        b.iter(|| {
+            headers
+                .iter()
+                .filter_map(|(id, desc)| found(regex.parse_record(id, Some(desc))))
+                .sum::<f64>()
+        })
+    });
+
+    let preset = HeaderField::preset(HeaderFormat::Logan);
+    group.bench_function("byte parser", |b| {
+        b.iter(|| {
+            headers
+                .iter()
+                .filter_map(|(id, desc)| found(preset.parse_record(id, Some(desc))))
+                .sum::<f64>()
+        })
+    });
+    group.finish();
+}
+
+fn fast_path_spectrum(c: &mut Criterion) {
+    let records: Vec<Record> = headers()
+        .into_iter()
+        .map(|(id, desc)| {
+            let seq = b"ACGTTGCAAGGCTTACCGATAGCTAGGCATCGACTTAGCAGTCA".to_vec();
+            Record::Fasta(fasta::Record::with_attrs(&id, Some(&desc), &seq))
+        })
+        .collect();
+    let builder = SpectrumBuilder::new(31);
+
+    let mut group = c.benchmark_group("spectrum");
+    group.throughput(Throughput::Elements(RECORDS as u64));
+    group.bench_function("fast path", |b| {
+        b.iter(|| {
+            let records = records.iter().cloned().map(Ok);
+            black_box(builder.build_from_records(records).unwrap())
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, header_parsing, fast_path_spectrum);
+criterion_main!(benches);
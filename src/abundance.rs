@@ -0,0 +1,511 @@
+//! Abundances weighting the k-mers of each record
+
+use clap::ValueEnum;
+use regex::Regex;
+use std::cell::RefCell;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::input::Record;
+
+/// Rounding policy applied to header abundances before they weight k-mers
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Rounding {
+    /// Keep the integer part only (behaviour of versions <= 0.2.0)
+    Floor,
+    /// Round to the nearest integer
+    Round,
+    /// Round up to the next integer
+    Ceil,
+    /// Keep the fractional value
+    Float,
+}
+
+impl Rounding {
+    /// Applies the rounding policy to an abundance
+    pub fn apply(self, abundance: f64) -> f64 {
+        match self {
+            Rounding::Floor => abundance.floor(),
+            Rounding::Round => abundance.round(),
+            Rounding::Ceil => abundance.ceil(),
+            Rounding::Float => abundance,
+        }
+    }
+}
+
+/// How the abundances of the occurrences of a k-mer are combined into its count
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Aggregation {
+    /// Sum of the abundances of the sequences the k-mer occurs in
+    #[default]
+    Sum,
+    /// Highest abundance of the sequences the k-mer occurs in
+    Max,
+    /// Average abundance of the sequences the k-mer occurs in
+    Mean,
+    /// Number of occurrences of the k-mer, whatever the abundances (distinct-sequence spectrum)
+    Unweighted,
+}
+
+/// Running aggregate of the abundances of the occurrences of a k-mer
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Tally {
+    value: f64,
+    occurrences: u64,
+}
+
+impl Aggregation {
+    /// Adds an occurrence of a k-mer with the given abundance to its tally
+    pub fn add(self, tally: &mut Tally, abundance: f64) {
+        tally.value = match self {
+            Aggregation::Sum | Aggregation::Mean => tally.value + abundance,
+            Aggregation::Max if tally.occurrences == 0 => abundance,
+            Aggregation::Max => tally.value.max(abundance),
+            Aggregation::Unweighted => tally.value + 1.0,
+        };
+        tally.occurrences += 1;
+    }
+
+    /// Count of a k-mer from its tally
+    pub fn count(self, tally: &Tally) -> f64 {
+        match self {
+            Aggregation::Mean => tally.value / tally.occurrences as f64,
+            _ => tally.value,
+        }
+    }
+}
+
+/// Assembler whose FASTA headers carry the abundance of each sequence
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum HeaderFormat {
+    /// Logan unitigs and contigs: `ka:f:7.304`
+    Logan,
+    /// BCALM unitigs: `km:f:2.5`, the average k-mer abundance
+    Bcalm,
+    /// SPAdes contigs: `_cov_12.5` in the identifier
+    Spades,
+    /// Megahit contigs: `multi=2.0000`
+    Megahit,
+}
+
+/// Abundance looked for in a FASTA header
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HeaderAbundance {
+    /// The abundance of the field
+    Found(f64),
+    /// The header has no abundance field
+    Missing,
+    /// The header has the abundance field, but its value is not a number
+    Malformed,
+}
+
+impl HeaderAbundance {
+    /// Combines the abundances looked for in two parts of a header: the first one found wins,
+    /// and a malformed field is only reported when no part has a valid one
+    fn or(self, other: HeaderAbundance) -> HeaderAbundance {
+        match (self, other) {
+            (HeaderAbundance::Found(_), _) => self,
+            (_, HeaderAbundance::Found(_)) => other,
+            (HeaderAbundance::Malformed, _) => self,
+            _ => other,
+        }
+    }
+}
+
+thread_local! {
+    /// Whole header of the record being parsed, for regular expressions
+    static HEADER: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// How the abundance field is found in a header
+#[derive(Clone, Debug)]
+enum Matcher {
+    /// Field starting with one of the keys and followed by a number, parsed byte by byte
+    Keys {
+        keys: Vec<String>,
+        /// Whether the key must start the header or follow a whitespace
+        token_start: bool,
+    },
+    /// Custom regular expression, whose first capture group (or whole match) is the abundance
+    Regex(Regex),
+}
+
+/// Field of a FASTA header holding the abundance of the sequence
+///
+/// Presets and tags are parsed without allocating, as they are looked for in every record.
+#[derive(Clone, Debug)]
+pub struct HeaderField {
+    matcher: Matcher,
+    description: String,
+}
+
+impl HeaderField {
+    /// Abundance field of an assembler's headers
+    pub fn preset(format: HeaderFormat) -> Self {
+        let (key, name) = match format {
+            HeaderFormat::Logan => ("ka:f:", "logan"),
+            HeaderFormat::Bcalm => ("km:f:", "bcalm"),
+            HeaderFormat::Spades => ("_cov_", "spades"),
+            HeaderFormat::Megahit => ("multi=", "megahit"),
+        };
+        HeaderField {
+            matcher: Matcher::Keys {
+                keys: vec![key.to_string()],
+                token_start: false,
+            },
+            description: name.to_string(),
+        }
+    }
+
+    /// SAM-style optional field `TAG:f:value` or `TAG:i:value`, with a two-character tag
+    /// such as `KC`
+    pub fn from_tag(tag: &str) -> Result<Self, String> {
+        let mut chars = tag.chars();
+        let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+            && chars.next().is_some_and(|c| c.is_ascii_alphanumeric())
+            && chars.next().is_none();
+        if !valid {
+            return Err(format!(
+                "invalid tag `{}`: expected two letters or digits, such as `KC`",
+                tag
+            ));
+        }
+        Ok(HeaderField {
+            matcher: Matcher::Keys {
+                keys: vec![format!("{}:f:", tag), format!("{}:i:", tag)],
+                token_start: true,
+            },
+            description: format!("tag:{}", tag),
+        })
+    }
+
+    /// Custom regular expression, whose first capture group (or whole match, without group)
+    /// is the abundance; the field is malformed when it matches something else than a number
+    ///
+    /// The regular expression is compiled once, and matched against the whole header of each
+    /// record: its identifier and its description, separated by a space.
+    pub fn from_regex(pattern: &str) -> Result<Self, regex::Error> {
+        Ok(HeaderField {
+            matcher: Matcher::Regex(Regex::new(pattern)?),
+            description: format!("regex:{}", pattern),
+        })
+    }
+
+    /// Looks for the abundance in a header line, without its leading `>`
+    pub fn parse(&self, header: &str) -> HeaderAbundance {
+        self.parse_part(header)
+    }
+
+    /// Looks for the abundance in the identifier of a record, then in its description, or for a
+    /// regular expression in its whole header
+    pub fn parse_record(&self, id: &str, desc: Option<&str>) -> HeaderAbundance {
+        if let (Matcher::Regex(_), Some(desc)) = (&self.matcher, desc) {
+            // The pattern may be anchored, or span the identifier and the description: they are
+            // joined in a buffer reused across records
+            return HEADER.with_borrow_mut(|header| {
+                header.clear();
+                header.push_str(id);
+                header.push(' ');
+                header.push_str(desc);
+                self.parse_part(header)
+            });
+        }
+        let abundance = self.parse_part(id);
+        match (abundance, desc) {
+            (HeaderAbundance::Found(_), _) | (_, None) => abundance,
+            (_, Some(desc)) => abundance.or(self.parse_part(desc)),
+        }
+    }
+
+    /// Extracts the abundance from a header, or `None` if the field is missing or not a number
+    pub fn extract(&self, header: &str) -> Option<f64> {
+        match self.parse(header) {
+            HeaderAbundance::Found(abundance) => Some(abundance),
+            _ => None,
+        }
+    }
+
+    /// Looks for the abundance in a header, or in its identifier or description alone
+    fn parse_part(&self, part: &str) -> HeaderAbundance {
+        match &self.matcher {
+            Matcher::Keys { keys, token_start } => {
+                let mut abundance = HeaderAbundance::Missing;
+                for key in keys {
+                    for (start, _) in part.match_indices(key.as_str()) {
+                        // Each part starts after a whitespace, or at the start of the header
+                        let at_token_start =
+                            start == 0 || part.as_bytes()[start - 1].is_ascii_whitespace();
+                        if *token_start && !at_token_start {
+                            continue;
+                        }
+                        match parse_number(&part[start + key.len()..]) {
+                            Some(value) => return HeaderAbundance::Found(value),
+                            None => abundance = HeaderAbundance::Malformed,
+                        }
+                    }
+                }
+                abundance
+            }
+            Matcher::Regex(regex) => match regex.captures(part) {
+                Some(captures) => {
+                    let value = captures.get(1).or_else(|| captures.get(0));
+                    match value.and_then(|value| value.as_str().parse().ok()) {
+                        Some(abundance) => HeaderAbundance::Found(abundance),
+                        None => HeaderAbundance::Malformed,
+                    }
+                }
+                None => HeaderAbundance::Missing,
+            },
+        }
+    }
+}
+
+/// Parses the number starting a string: digits, optionally followed by `.` and digits
+fn parse_number(text: &str) -> Option<f64> {
+    let bytes = text.as_bytes();
+    let digits = |from: usize| {
+        bytes[from..]
+            .iter()
+            .take_while(|byte| byte.is_ascii_digit())
+            .count()
+    };
+    let integer = digits(0);
+    if integer == 0 {
+        return None;
+    }
+    let mut end = integer;
+    if bytes.get(end) == Some(&b'.') {
+        let fraction = digits(end + 1);
+        if fraction > 0 {
+            end += 1 + fraction;
+        }
+    }
+    text[..end].parse().ok()
+}
+
+impl Default for HeaderField {
+    fn default() -> Self {
+        HeaderField::preset(HeaderFormat::Logan)
+    }
+}
+
+impl fmt::Display for HeaderField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.description)
+    }
+}
+
+/// What to do with FASTA records whose header has no abundance
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MissingAbundance {
+    /// Ignore the record
+    #[default]
+    Skip,
+    /// Weight the k-mers of the record with this abundance
+    Value(f64),
+    /// Stop with an error
+    Error,
+}
+
+impl FromStr for MissingAbundance {
+    type Err = String;
+
+    /// Parses `skip`, `error` or an abundance
+    fn from_str(policy: &str) -> Result<Self, String> {
+        match policy {
+            "skip" => Ok(MissingAbundance::Skip),
+            "error" => Ok(MissingAbundance::Error),
+            value => match value.parse::<f64>() {
+                Ok(abundance) if abundance.is_finite() && abundance >= 0.0 => {
+                    Ok(MissingAbundance::Value(abundance))
+                }
+                _ => Err(format!(
+                    "expected `skip`, `error` or a positive abundance, got `{}`",
+                    policy
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for MissingAbundance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MissingAbundance::Skip => f.write_str("skip"),
+            MissingAbundance::Value(abundance) => write!(f, "{}", abundance),
+            MissingAbundance::Error => f.write_str("error"),
+        }
+    }
+}
+
+/// Where the abundance weighting the k-mers of a FASTA record comes from
+///
+/// FASTQ records are raw reads and always weight their k-mers by 1.
+#[derive(Clone, Debug)]
+pub enum AbundanceSource {
+    /// Header field, rounded with the given policy
+    Header(HeaderField, Rounding),
+    /// The same abundance for every record
+    Constant(f64),
+}
+
+impl Default for AbundanceSource {
+    fn default() -> Self {
+        AbundanceSource::Header(HeaderField::default(), Rounding::Float)
+    }
+}
+
+impl AbundanceSource {
+    /// Abundance of a record, telling why it has none
+    pub fn abundance(&self, record: &Record) -> HeaderAbundance {
+        if let Record::Fastq(_) = record {
+            return HeaderAbundance::Found(1.0);
+        }
+        match self {
+            AbundanceSource::Header(field, rounding) => {
+                match field.parse_record(record.id(), record.desc()) {
+                    HeaderAbundance::Found(abundance) => {
+                        HeaderAbundance::Found(rounding.apply(abundance))
+                    }
+                    other => other,
+                }
+            }
+            AbundanceSource::Constant(abundance) => HeaderAbundance::Found(*abundance),
+        }
+    }
+
+    /// Abundance weighting the k-mers of a record, or `None` if the header has none
+    pub fn weight(&self, record: &Record) -> Option<f64> {
+        match self.abundance(record) {
+            HeaderAbundance::Found(abundance) => Some(abundance),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bio::io::{fasta, fastq};
+
+    #[test]
+    fn fastq_reads_weigh_their_kmers_by_one() {
+        let source = AbundanceSource::default();
+        let read = Record::Fastq(fastq::Record::with_attrs("read1", None, b"ACGT", b"IIII"));
+        assert_eq!(source.weight(&read), Some(1.0));
+        let contig = Record::Fasta(fasta::Record::with_attrs(
+            "SRR1_1",
+            Some("ka:f:2.5"),
+            b"ACGT",
+        ));
+        assert_eq!(source.weight(&contig), Some(2.5));
+        assert_eq!(
+            AbundanceSource::Header(HeaderField::default(), Rounding::Floor).weight(&contig),
+            Some(2.0)
+        );
+        let bare = Record::Fasta(fasta::Record::with_attrs("SRR1_2", None, b"ACGT"));
+        assert_eq!(source.weight(&bare), None);
+        assert_eq!(AbundanceSource::Constant(3.0).weight(&bare), Some(3.0));
+    }
+
+    #[test]
+    fn header_fields_extract_abundances() {
+        let spades = HeaderField::preset(HeaderFormat::Spades);
+        assert_eq!(spades.extract("NODE_1_length_120_cov_12.5 "), Some(12.5));
+        let megahit = HeaderField::preset(HeaderFormat::Megahit);
+        assert_eq!(
+            megahit.extract("k141_3 flag=1 multi=2.0000 len=40"),
+            Some(2.0)
+        );
+        let tag = HeaderField::from_tag("KC").unwrap();
+        assert_eq!(tag.extract("12 LN:i:40 KC:i:80 km:f:2.0"), Some(80.0));
+        assert_eq!(tag.extract("12 xKC:i:80"), None);
+        assert!(HeaderField::from_tag("K").is_err());
+        let regex = HeaderField::from_regex(r"depth=(\S+)").unwrap();
+        assert_eq!(regex.extract("contig_3 depth=7.25"), Some(7.25));
+        assert_eq!(regex.extract("contig_3 depth=high"), None);
+        assert_eq!(
+            HeaderField::from_regex(r"\d+$")
+                .unwrap()
+                .extract("contig 42"),
+            Some(42.0)
+        );
+    }
+
+    #[test]
+    fn aggregations_combine_abundances() {
+        let counts = [
+            (Aggregation::Sum, 7.5),
+            (Aggregation::Max, 4.0),
+            (Aggregation::Mean, 2.5),
+            (Aggregation::Unweighted, 3.0),
+        ];
+        for (aggregation, count) in counts {
+            let mut tally = Tally::default();
+            for abundance in [2.5, 4.0, 1.0] {
+                aggregation.add(&mut tally, abundance);
+            }
+            assert_eq!(aggregation.count(&tally), count, "{:?}", aggregation);
+        }
+    }
+
+    #[test]
+    fn regex_matches_the_whole_header() {
+        let anchored = HeaderField::from_regex(r"^ka:f:([0-9.]+)").unwrap();
+        assert_eq!(
+            anchored.parse_record("ka:f:4.5", Some("len=12")),
+            HeaderAbundance::Found(4.5)
+        );
+        // The description does not start the header
+        assert_eq!(
+            anchored.parse_record("SRR1_12", Some("ka:f:4.5")),
+            HeaderAbundance::Missing
+        );
+
+        let spanning = HeaderField::from_regex(r"_12 cov=(\S+)").unwrap();
+        assert_eq!(
+            spanning.parse_record("SRR1_12", Some("cov=7.25 len=40")),
+            HeaderAbundance::Found(7.25)
+        );
+        assert_eq!(
+            spanning.parse_record("SRR1_12", Some("cov=high")),
+            HeaderAbundance::Malformed
+        );
+        assert_eq!(
+            spanning.parse_record("SRR1_12", None),
+            HeaderAbundance::Missing
+        );
+
+        let whole = HeaderField::from_regex(r"^\S+ depth=(\S+)$").unwrap();
+        assert_eq!(
+            whole.parse_record("contig_3", Some("depth=12")),
+            whole.parse("contig_3 depth=12")
+        );
+        assert_eq!(
+            whole.parse_record("contig_3", Some("depth=12")),
+            HeaderAbundance::Found(12.0)
+        );
+    }
+
+    #[test]
+    fn presets_and_tags_are_looked_for_in_each_part() {
+        let logan = HeaderField::preset(HeaderFormat::Logan);
+        assert_eq!(
+            logan.parse_record("SRR1_12", Some("ka:f:7.304 L:+:13:-")),
+            HeaderAbundance::Found(7.304)
+        );
+        assert_eq!(
+            logan.parse_record("SRR1_12", Some("ka:f:x")),
+            HeaderAbundance::Malformed
+        );
+        let tag = HeaderField::from_tag("KC").unwrap();
+        assert_eq!(
+            tag.parse_record("12", Some("LN:i:40 KC:i:80")),
+            HeaderAbundance::Found(80.0)
+        );
+        assert_eq!(
+            tag.parse_record("12", Some("xKC:i:80")),
+            HeaderAbundance::Missing
+        );
+    }
+}
@@ -0,0 +1,171 @@
+//! Comparison of two k-mer spectra, bin by bin and with distances between their distributions
+//!
+//! Each spectrum is normalized into the distribution of its distinct k-mers over the bins. The
+//! Kullback-Leibler divergence adds one pseudo k-mer to every bin occupied in either spectrum,
+//! so that it stays finite when a bin is empty in the second one. Divergences are in nats.
+
+use std::collections::BTreeMap;
+
+use crate::spectrum::{bin_label, bin_lower_bound, Spectrum};
+
+/// Number of distinct k-mers of a bin in both spectra
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BinComparison {
+    /// Histogram bin
+    pub bin: u64,
+    /// Number of k-mers of the bin in the first spectrum
+    pub first: u64,
+    /// Number of k-mers of the bin in the second spectrum
+    pub second: u64,
+}
+
+impl BinComparison {
+    /// Number of k-mers of the second spectrum minus the one of the first
+    pub fn difference(&self) -> i64 {
+        self.second as i64 - self.first as i64
+    }
+}
+
+/// Bin-by-bin differences and distances between two spectra
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comparison {
+    /// Distinct k-mers of the first spectrum
+    pub first_kmers: u64,
+    /// Distinct k-mers of the second spectrum
+    pub second_kmers: u64,
+    /// Sum of the absolute differences between the two distributions, from 0 to 2
+    pub l1_distance: f64,
+    /// Kullback-Leibler divergence of the second distribution from the first one
+    pub kl_divergence: f64,
+    /// Jensen-Shannon divergence between the two distributions, from 0 to ln 2
+    pub jensen_shannon: f64,
+    /// Bins occupied in either spectrum, by increasing frequency
+    pub bins: Vec<BinComparison>,
+    /// Width of the bins of the spectra
+    pub bin_width: Option<f64>,
+}
+
+impl Comparison {
+    /// Lower bound of the k-mer counts of a bin
+    pub fn frequency(&self, bin: u64) -> f64 {
+        bin_lower_bound(bin, self.bin_width)
+    }
+
+    /// Formats the lower bound of the k-mer counts of a bin
+    pub fn label(&self, bin: u64) -> String {
+        bin_label(bin, self.bin_width)
+    }
+}
+
+/// Compares two spectra with the same binning, or returns `None` if their bin widths differ
+pub fn compare_spectra(first: &Spectrum, second: &Spectrum) -> Option<Comparison> {
+    if first.bin_width() != second.bin_width() {
+        return None;
+    }
+    let mut kmers: BTreeMap<u64, (u64, u64)> = BTreeMap::new();
+    for (bin, first_kmers) in first.bins() {
+        kmers.entry(bin).or_default().0 = first_kmers;
+    }
+    for (bin, second_kmers) in second.bins() {
+        kmers.entry(bin).or_default().1 = second_kmers;
+    }
+    let bins: Vec<BinComparison> = kmers
+        .into_iter()
+        .map(|(bin, (first, second))| BinComparison { bin, first, second })
+        .collect();
+
+    let (first_kmers, second_kmers) = (first.distinct_kmers(), second.distinct_kmers());
+    let fraction = |kmers: u64, total: u64| match total {
+        0 => 0.0,
+        total => kmers as f64 / total as f64,
+    };
+    let mut l1_distance = 0.0;
+    let mut kl_divergence = 0.0;
+    let mut jensen_shannon = 0.0;
+    let smoothed = |kmers: u64, total: u64| (kmers + 1) as f64 / (total + bins.len() as u64) as f64;
+    for comparison in &bins {
+        let p = fraction(comparison.first, first_kmers);
+        let q = fraction(comparison.second, second_kmers);
+        l1_distance += (p - q).abs();
+
+        let (p_smoothed, q_smoothed) = (
+            smoothed(comparison.first, first_kmers),
+            smoothed(comparison.second, second_kmers),
+        );
+        kl_divergence += p_smoothed * (p_smoothed / q_smoothed).ln();
+
+        let m = (p + q) / 2.0;
+        for x in [p, q] {
+            if x > 0.0 {
+                jensen_shannon += x * (x / m).ln() / 2.0;
+            }
+        }
+    }
+
+    Some(Comparison {
+        first_kmers,
+        second_kmers,
+        l1_distance,
+        kl_divergence,
+        jensen_shannon,
+        bins,
+        bin_width: first.bin_width(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spectrum(bins: &[(u64, u64)], bin_width: Option<f64>) -> Spectrum {
+        let mut spectrum = Spectrum::new(bin_width);
+        for &(bin, kmers) in bins {
+            spectrum.add_bin(bin, kmers);
+        }
+        spectrum
+    }
+
+    #[test]
+    fn compares_the_distributions_of_distinct_kmers() {
+        let first = spectrum(&[(1, 2), (2, 2)], None);
+        let second = spectrum(&[(1, 4)], None);
+        let comparison = compare_spectra(&first, &second).unwrap();
+        assert_eq!((comparison.first_kmers, comparison.second_kmers), (4, 4));
+        assert_eq!(
+            comparison.bins,
+            [
+                BinComparison {
+                    bin: 1,
+                    first: 2,
+                    second: 4
+                },
+                BinComparison {
+                    bin: 2,
+                    first: 2,
+                    second: 0
+                },
+            ]
+        );
+        assert_eq!(comparison.bins[1].difference(), -2);
+
+        // p = (1/2, 1/2) and q = (1, 0), smoothed into (1/2, 1/2) and (5/6, 1/6)
+        assert!((comparison.l1_distance - 1.0).abs() < 1e-12);
+        let kl_divergence = (0.5 * (0.5f64 / (5.0 / 6.0)).ln()) + 0.5 * 3f64.ln();
+        assert!((comparison.kl_divergence - kl_divergence).abs() < 1e-12);
+        // m = (3/4, 1/4)
+        let jensen_shannon = 0.25 * (2f64 / 3.0).ln() + 0.25 * 2f64.ln() + 0.5 * (4f64 / 3.0).ln();
+        assert!((comparison.jensen_shannon - jensen_shannon).abs() < 1e-12);
+    }
+
+    #[test]
+    fn identical_spectra_are_at_no_distance() {
+        let first = spectrum(&[(1, 3), (5, 7)], Some(0.5));
+        let comparison = compare_spectra(&first, &first).unwrap();
+        assert_eq!(comparison.l1_distance, 0.0);
+        assert_eq!(comparison.kl_divergence, 0.0);
+        assert_eq!(comparison.jensen_shannon, 0.0);
+        assert_eq!(comparison.label(5), "2.5");
+
+        assert_eq!(compare_spectra(&first, &spectrum(&[(1, 3)], None)), None);
+    }
+}
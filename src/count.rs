@@ -0,0 +1,593 @@
+//! Weighted k-mer counting, either on the calling thread or sharded across worker threads
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+use crate::abundance::{Aggregation, Tally};
+use crate::input::Record;
+use crate::kmer::{count_valid_kmers, generate_encoded_kmers, kmer_hash, Kmer};
+use crate::spectrum::Spectrum;
+
+/// Number of records sent at once to a worker thread
+const BATCH_SIZE: usize = 1024;
+
+/// Weighted k-mer counts, split into disjoint shards
+pub struct KmerCounts<K> {
+    shards: Vec<HashMap<K, Tally>>,
+    aggregation: Aggregation,
+}
+
+impl<K: Kmer> KmerCounts<K> {
+    /// Weighted count of a k-mer, or `None` if it does not occur
+    pub fn get(&self, kmer: &K) -> Option<f64> {
+        self.shards[shard_of(kmer, self.shards.len())]
+            .get(kmer)
+            .map(|tally| self.aggregation.count(tally))
+    }
+
+    /// k-mers and their weighted counts, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&K, f64)> {
+        self.shards
+            .iter()
+            .flat_map(|shard| shard.iter())
+            .map(|(kmer, tally)| (kmer, self.aggregation.count(tally)))
+    }
+
+    /// Weighted counts of all k-mers, in no particular order
+    pub fn values(&self) -> impl Iterator<Item = f64> + '_ {
+        self.shards
+            .iter()
+            .flat_map(|shard| shard.values())
+            .map(|tally| self.aggregation.count(tally))
+    }
+
+    /// Keeps only the k-mers whose weighted count satisfies `keep`
+    pub fn retain(&mut self, keep: impl Fn(f64) -> bool) {
+        let aggregation = self.aggregation;
+        for shard in &mut self.shards {
+            shard.retain(|_, tally| keep(aggregation.count(tally)));
+        }
+    }
+
+    /// Number of distinct k-mers
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.len()).sum()
+    }
+
+    /// Whether no k-mer was counted
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.is_empty())
+    }
+}
+
+/// Shard a k-mer belongs to, independently of the hash maps' random state
+fn shard_of<K: Kmer>(kmer: &K, shards: usize) -> usize {
+    (kmer_hash(kmer) % shards as u64) as usize
+}
+
+/// Counts the k-mers of all records on the calling thread
+///
+/// Each k-mer of a record is weighted by `weight(record)`, and the weights of its occurrences
+/// are combined according to `aggregation`; records without a weight are skipped. The first
+/// and last `trim` bases of each record are ignored.
+pub fn count_kmers<K, I, W>(
+    records: I,
+    k: usize,
+    canonical: bool,
+    trim: usize,
+    weight: W,
+    aggregation: Aggregation,
+) -> io::Result<KmerCounts<K>>
+where
+    K: Kmer,
+    I: Iterator<Item = io::Result<Record>>,
+    W: Fn(&Record) -> Option<f64>,
+{
+    let mut kmer_counts: HashMap<K, Tally> = HashMap::new();
+
+    for result in records {
+        let record = result?;
+        if let Some(abundance) = weight(&record) {
+            for kmer in generate_encoded_kmers::<K>(record.seq(), k, canonical, trim) {
+                aggregation.add(kmer_counts.entry(kmer).or_default(), abundance);
+            }
+        }
+    }
+
+    Ok(KmerCounts {
+        shards: vec![kmer_counts],
+        aggregation,
+    })
+}
+
+/// Counts the k-mers of all records on the calling thread, separately for the group given
+/// along with each record
+///
+/// Each group is counted as by [`count_kmers`], including the groups whose records are all
+/// skipped.
+pub fn count_kmers_by_group<K, G, I, W>(
+    records: I,
+    k: usize,
+    canonical: bool,
+    trim: usize,
+    weight: W,
+    aggregation: Aggregation,
+) -> io::Result<BTreeMap<G, KmerCounts<K>>>
+where
+    K: Kmer,
+    G: Ord,
+    I: Iterator<Item = io::Result<(G, Record)>>,
+    W: Fn(&Record) -> Option<f64>,
+{
+    let mut groups: BTreeMap<G, HashMap<K, Tally>> = BTreeMap::new();
+
+    for result in records {
+        let (group, record) = result?;
+        let kmer_counts = groups.entry(group).or_default();
+        if let Some(abundance) = weight(&record) {
+            for kmer in generate_encoded_kmers::<K>(record.seq(), k, canonical, trim) {
+                aggregation.add(kmer_counts.entry(kmer).or_default(), abundance);
+            }
+        }
+    }
+
+    Ok(groups
+        .into_iter()
+        .map(|(group, kmer_counts)| {
+            let kmer_counts = KmerCounts {
+                shards: vec![kmer_counts],
+                aggregation,
+            };
+            (group, kmer_counts)
+        })
+        .collect())
+}
+
+/// Builds the spectrum directly from sequence lengths and abundances, without a hash table
+///
+/// Valid when every k-mer occurs in a single sequence, which holds for Logan unitigs and
+/// contigs when k is the assembly k: each k-mer's count is then its sequence's abundance.
+/// The first and last `trim` bases of each record are ignored.
+pub fn spectrum_from_lengths<I, W>(
+    records: I,
+    k: usize,
+    trim: usize,
+    weight: W,
+    bin_width: Option<f64>,
+) -> io::Result<Spectrum>
+where
+    I: Iterator<Item = io::Result<Record>>,
+    W: Fn(&Record) -> Option<f64>,
+{
+    let mut spectrum = Spectrum::new(bin_width);
+
+    for result in records {
+        let record = result?;
+        if let Some(abundance) = weight(&record) {
+            let kmers = count_valid_kmers(record.seq(), k, trim);
+            if kmers > 0 {
+                spectrum.add(abundance, kmers);
+            }
+        }
+    }
+
+    Ok(spectrum)
+}
+
+/// Builds the spectrum of the group given along with each record directly from sequence lengths
+/// and abundances, as [`spectrum_from_lengths`]
+pub fn spectrum_from_lengths_by_group<G, I, W>(
+    records: I,
+    k: usize,
+    trim: usize,
+    weight: W,
+    bin_width: Option<f64>,
+) -> io::Result<BTreeMap<G, Spectrum>>
+where
+    G: Ord,
+    I: Iterator<Item = io::Result<(G, Record)>>,
+    W: Fn(&Record) -> Option<f64>,
+{
+    let mut spectra = BTreeMap::new();
+
+    for result in records {
+        let (group, record) = result?;
+        let spectrum = spectra
+            .entry(group)
+            .or_insert_with(|| Spectrum::new(bin_width));
+        if let Some(abundance) = weight(&record) {
+            let kmers = count_valid_kmers(record.seq(), k, trim);
+            if kmers > 0 {
+                spectrum.add(abundance, kmers);
+            }
+        }
+    }
+
+    Ok(spectra)
+}
+
+/// Counts the k-mers of all records with `threads` extraction workers and `threads` counting shards
+///
+/// Records are parsed on the calling thread and sent in batches to the extraction workers,
+/// which split the weighted k-mers of each batch by shard. Each shard applies the batches in
+/// input order, so every k-mer count is aggregated in the same order as in [`count_kmers`] and
+/// the result is identical to the single-threaded one, fractional abundances included.
+/// The parser stays at most two batches per thread ahead of the slowest shard, which bounds
+/// the batches a shard keeps out of order.
+pub fn count_kmers_parallel<K, I, W>(
+    records: I,
+    k: usize,
+    canonical: bool,
+    trim: usize,
+    weight: W,
+    aggregation: Aggregation,
+    threads: usize,
+) -> io::Result<KmerCounts<K>>
+where
+    K: Kmer,
+    I: Iterator<Item = io::Result<Record>>,
+    W: Fn(&Record) -> Option<f64> + Sync,
+{
+    let (batch_sender, batch_receiver) = mpsc::sync_channel::<(usize, Vec<Record>)>(2 * threads);
+    let batch_receiver = Mutex::new(batch_receiver);
+    let (shard_senders, shard_receivers): (Vec<_>, Vec<_>) = (0..threads)
+        .map(|_| mpsc::sync_channel::<(usize, Vec<(K, f64)>)>(2 * threads))
+        .unzip();
+
+    let progress = Progress::new(threads);
+
+    thread::scope(|scope| {
+        let counters: Vec<_> = shard_receivers
+            .into_iter()
+            .enumerate()
+            .map(|(shard, receiver)| {
+                let progress = &progress;
+                scope.spawn(move || count_shard(receiver, shard, aggregation, progress))
+            })
+            .collect();
+
+        for _ in 0..threads {
+            let shard_senders = shard_senders.clone();
+            let batch_receiver = &batch_receiver;
+            let weight = &weight;
+            let progress = &progress;
+            scope.spawn(move || loop {
+                let _guard = AbortOnPanic(progress);
+                let Ok((index, batch)) = batch_receiver.lock().unwrap().recv() else {
+                    break;
+                };
+                let mut buffers: Vec<Vec<(K, f64)>> = vec![Vec::new(); shard_senders.len()];
+                for record in &batch {
+                    if let Some(abundance) = weight(record) {
+                        for kmer in generate_encoded_kmers::<K>(record.seq(), k, canonical, trim) {
+                            buffers[shard_of(&kmer, threads)].push((kmer, abundance));
+                        }
+                    }
+                }
+                // Empty buffers are sent too, so that every shard sees every batch index
+                for (sender, buffer) in shard_senders.iter().zip(buffers) {
+                    let _ = sender.send((index, buffer));
+                }
+            });
+        }
+        drop(shard_senders);
+
+        // Parse records on this thread; dropping the sender lets the workers finish
+        let parsed = send_batches(records, batch_sender, &progress, 2 * threads);
+
+        let shards = counters
+            .into_iter()
+            .map(|counter| counter.join().unwrap())
+            .collect();
+        parsed.map(|_| KmerCounts {
+            shards,
+            aggregation,
+        })
+    })
+}
+
+/// Number of batches applied by each shard, which the parser waits on so that shards never keep
+/// more than a window of batches while they wait for an earlier one
+struct Progress {
+    /// Batches applied by each shard, or `None` once a worker panicked
+    applied: Mutex<Option<Vec<usize>>>,
+    advanced: Condvar,
+}
+
+impl Progress {
+    fn new(shards: usize) -> Self {
+        Progress {
+            applied: Mutex::new(Some(vec![0; shards])),
+            advanced: Condvar::new(),
+        }
+    }
+
+    /// Records that a shard applied every batch before `next`
+    fn advance(&self, shard: usize, next: usize) {
+        if let Some(applied) = self.applied.lock().unwrap().as_mut() {
+            applied[shard] = next;
+        }
+        self.advanced.notify_all();
+    }
+
+    /// Stops the parser, whose batches may no longer all be applied
+    fn abort(&self) {
+        *self.applied.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        self.advanced.notify_all();
+    }
+
+    /// Waits until every shard applied every batch before `index`; returns `false` if aborted
+    fn wait(&self, index: usize) -> bool {
+        let mut applied = self.applied.lock().unwrap();
+        loop {
+            match applied.as_ref() {
+                None => return false,
+                Some(shards) if shards.iter().all(|&next| next >= index) => return true,
+                Some(_) => applied = self.advanced.wait(applied).unwrap(),
+            }
+        }
+    }
+}
+
+/// Aborts the progress if the thread holding it panics, so that the parser does not wait for
+/// batches that will never be applied
+struct AbortOnPanic<'a>(&'a Progress);
+
+impl Drop for AbortOnPanic<'_> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.0.abort();
+        }
+    }
+}
+
+/// Groups records into numbered batches and sends them to the extraction workers, at most
+/// `window` batches ahead of the slowest shard
+fn send_batches<I>(
+    records: I,
+    sender: SyncSender<(usize, Vec<Record>)>,
+    progress: &Progress,
+    window: usize,
+) -> io::Result<()>
+where
+    I: Iterator<Item = io::Result<Record>>,
+{
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut index: usize = 0;
+    for result in records {
+        batch.push(result?);
+        if batch.len() == BATCH_SIZE {
+            let full = std::mem::replace(&mut batch, Vec::with_capacity(BATCH_SIZE));
+            if !progress.wait(index.saturating_sub(window)) || sender.send((index, full)).is_err() {
+                break;
+            }
+            index += 1;
+        }
+    }
+    if !batch.is_empty() && progress.wait(index.saturating_sub(window)) {
+        let _ = sender.send((index, batch));
+    }
+    Ok(())
+}
+
+/// Aggregates the weighted k-mers of one shard, applying batches in input order
+fn count_shard<K: Kmer>(
+    receiver: Receiver<(usize, Vec<(K, f64)>)>,
+    shard: usize,
+    aggregation: Aggregation,
+    progress: &Progress,
+) -> HashMap<K, Tally> {
+    let _guard = AbortOnPanic(progress);
+    let mut kmer_counts: HashMap<K, Tally> = HashMap::new();
+    let mut pending = BTreeMap::new();
+    let mut next = 0;
+
+    for (index, buffer) in receiver {
+        pending.insert(index, buffer);
+        while let Some(buffer) = pending.remove(&next) {
+            for (kmer, abundance) in buffer {
+                aggregation.add(kmer_counts.entry(kmer).or_default(), abundance);
+            }
+            next += 1;
+        }
+        progress.advance(shard, next);
+    }
+    kmer_counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bio::io::fasta;
+
+    /// Short records over a 4-letter alphabet, so that k-mers are shared by many records,
+    /// with fractional abundances whose sums depend on the order they are added in
+    fn records() -> Vec<Record> {
+        let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+        (0..3 * BATCH_SIZE + 17)
+            .map(|i| {
+                let seq: Vec<u8> = (0..20 + i % 13)
+                    .map(|_| {
+                        state = state
+                            .wrapping_mul(6364136223846793005)
+                            .wrapping_add(1442695040888963407);
+                        b"ACGTN"[(state >> 33) as usize % 5]
+                    })
+                    .collect();
+                let desc = format!("ka:f:{}", 0.1 + i as f64 / 7.0);
+                Record::Fasta(fasta::Record::with_attrs(&i.to_string(), Some(&desc), &seq))
+            })
+            .collect()
+    }
+
+    fn weight(record: &Record) -> Option<f64> {
+        record.desc()?.strip_prefix("ka:f:")?.parse().ok()
+    }
+
+    /// Random sequences, whose 21-mers occur once as in an assembly, with a few `N`s
+    fn contigs() -> Vec<Record> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            state >> 33
+        };
+        (0..200)
+            .map(|i| {
+                let seq: Vec<u8> = (0..30 + next() % 100)
+                    .map(|_| match next() % 64 {
+                        0 => b'N',
+                        n => b"ACGT"[n as usize % 4],
+                    })
+                    .collect();
+                let desc = format!("ka:f:{}", 1.0 + (i % 17) as f64 / 4.0);
+                Record::Fasta(fasta::Record::with_attrs(&i.to_string(), Some(&desc), &seq))
+            })
+            .collect()
+    }
+
+    fn spectrum<K: Kmer>(counts: &KmerCounts<K>) -> Spectrum {
+        let mut spectrum = Spectrum::new(None);
+        for count in counts.values() {
+            spectrum.add(count, 1);
+        }
+        spectrum
+    }
+
+    #[test]
+    fn parallel_counts_match_single_threaded_ones() {
+        let records = records();
+        let aggregations = [
+            Aggregation::Sum,
+            Aggregation::Max,
+            Aggregation::Mean,
+            Aggregation::Unweighted,
+        ];
+        for aggregation in aggregations {
+            for canonical in [false, true] {
+                let single: KmerCounts<u64> = count_kmers(
+                    records.iter().cloned().map(Ok),
+                    5,
+                    canonical,
+                    0,
+                    weight,
+                    aggregation,
+                )
+                .unwrap();
+                for threads in [1, 2, 5] {
+                    let parallel: KmerCounts<u64> = count_kmers_parallel(
+                        records.iter().cloned().map(Ok),
+                        5,
+                        canonical,
+                        0,
+                        weight,
+                        aggregation,
+                        threads,
+                    )
+                    .unwrap();
+                    assert_eq!(parallel.len(), single.len());
+                    for (kmer, count) in single.iter() {
+                        // Bit-identical, so fractional sums are aggregated in the same order
+                        assert_eq!(
+                            parallel.get(kmer).map(f64::to_bits),
+                            Some(count.to_bits()),
+                            "{:?} with {} threads",
+                            aggregation,
+                            threads
+                        );
+                    }
+                    assert_eq!(spectrum(&parallel), spectrum(&single));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fast_path_matches_hash_counting() {
+        let contigs = contigs();
+        for (bin_width, trim) in [(None, 0), (Some(0.5), 0), (None, 5)] {
+            let records = contigs.iter().cloned().map(Ok);
+            let fast = spectrum_from_lengths(records, 21, trim, weight, bin_width).unwrap();
+            for canonical in [false, true] {
+                let counts: KmerCounts<u64> = count_kmers(
+                    contigs.iter().cloned().map(Ok),
+                    21,
+                    canonical,
+                    trim,
+                    weight,
+                    Aggregation::Sum,
+                )
+                .unwrap();
+                let mut spectrum = Spectrum::new(bin_width);
+                for count in counts.values() {
+                    spectrum.add(count, 1);
+                }
+                assert!(spectrum.len() > 3);
+                assert_eq!(fast, spectrum, "bin width {:?}, trim {}", bin_width, trim);
+            }
+        }
+    }
+
+    #[test]
+    fn grouped_counts_match_the_counts_of_each_group() {
+        let records = records();
+        let group = |record: &Record| record.seq().len() % 3;
+        let groups: BTreeMap<usize, KmerCounts<u64>> = count_kmers_by_group(
+            records
+                .iter()
+                .map(|record| Ok((group(record), record.clone()))),
+            5,
+            true,
+            0,
+            weight,
+            Aggregation::Mean,
+        )
+        .unwrap();
+        let spectra = spectrum_from_lengths_by_group(
+            records
+                .iter()
+                .map(|record| Ok((group(record), record.clone()))),
+            5,
+            0,
+            weight,
+            None,
+        )
+        .unwrap();
+        assert_eq!(groups.keys().collect::<Vec<_>>(), [&0, &1, &2]);
+        for (&value, counts) in &groups {
+            let members = || {
+                records
+                    .iter()
+                    .filter(move |record| group(record) == value)
+                    .cloned()
+                    .map(Ok)
+            };
+            let expected: KmerCounts<u64> =
+                count_kmers(members(), 5, true, 0, weight, Aggregation::Mean).unwrap();
+            assert_eq!(counts.len(), expected.len());
+            for (kmer, count) in expected.iter() {
+                assert_eq!(counts.get(kmer), Some(count));
+            }
+            assert_eq!(
+                spectra[&value],
+                spectrum_from_lengths(members(), 5, 0, weight, None).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn parallel_counting_stops_at_the_first_error() {
+        let records = records()
+            .into_iter()
+            .map(Ok)
+            .take(BATCH_SIZE + 3)
+            .chain([Err(io::Error::other("truncated input"))]);
+        let counts: io::Result<KmerCounts<u64>> =
+            count_kmers_parallel(records, 5, true, 0, weight, Aggregation::Sum, 3);
+        assert_eq!(counts.err().unwrap().to_string(), "truncated input");
+    }
+}
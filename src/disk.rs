@@ -0,0 +1,310 @@
+//! Memory-bounded k-mer counting, partitioning k-mers into temporary bucket files
+//!
+//! Weighted k-mers are first written to 256 bucket files according to one byte of their hash.
+//! Each bucket is then counted on its own in a hash map; a bucket whose hash map would not fit
+//! in the memory budget is split again on the next byte of the hash, unless splitting does not
+//! shrink it, as when it holds the occurrences of a single repeated k-mer.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::mem::size_of;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use crate::abundance::{Aggregation, Tally};
+use crate::input::Record;
+use crate::kmer::{generate_encoded_kmers, kmer_hash, Kmer};
+use crate::spectrum::Spectrum;
+
+/// Number of buckets a k-mer set is split into at each level
+const FANOUT: usize = 256;
+
+/// Number of hash bytes available to split buckets
+const MAX_DEPTH: usize = 8;
+
+/// Ratio between the memory used by a hash map and the size of its entries
+const MAP_OVERHEAD: usize = 2;
+
+/// Counts the k-mers of all records through bucket files in `tmp_dir`, keeping each
+/// bucket's hash map under `max_memory` bytes when possible, and returns the spectrum of those
+/// whose count is in `count_range`
+///
+/// The first and last `trim` bases of each record are ignored. Bucket files keep the weighted
+/// k-mers in input order, so the counts, and thus the spectrum, are identical to the in-memory
+/// ones.
+#[allow(clippy::too_many_arguments)]
+pub fn count_kmers_on_disk<K, I, W>(
+    records: I,
+    k: usize,
+    canonical: bool,
+    trim: usize,
+    weight: W,
+    aggregation: Aggregation,
+    max_memory: usize,
+    tmp_dir: &Path,
+    bin_width: Option<f64>,
+    count_range: &RangeInclusive<f64>,
+) -> io::Result<Spectrum>
+where
+    K: Kmer,
+    I: Iterator<Item = io::Result<Record>>,
+    W: Fn(&Record) -> Option<f64>,
+{
+    let dir = tempfile::Builder::new()
+        .prefix("logan_kmer_spectrum")
+        .tempdir_in(tmp_dir)?;
+    let buffer_size = (max_memory / 4 / FANOUT).clamp(4096, 1 << 16);
+
+    let mut buckets = create_buckets(dir.path(), "bucket", buffer_size)?;
+    let mut entry = vec![0; K::BYTES + 8];
+    for result in records {
+        let record = result?;
+        if let Some(abundance) = weight(&record) {
+            for kmer in generate_encoded_kmers::<K>(record.seq(), k, canonical, trim) {
+                encode_entry(&kmer, abundance, &mut entry);
+                buckets[bucket_of(&kmer, 0)].1.write_all(&entry)?;
+            }
+        }
+    }
+
+    let mut spectrum = Spectrum::new(bin_width);
+    for path in close_buckets(buckets)? {
+        count_bucket::<K>(
+            &path,
+            1,
+            aggregation,
+            count_range,
+            max_memory,
+            buffer_size,
+            &mut spectrum,
+        )?;
+    }
+    dir.close()?;
+    Ok(spectrum)
+}
+
+/// Creates the `FANOUT` bucket files `{prefix}_{index}` in `dir`
+fn create_buckets(
+    dir: &Path,
+    prefix: &str,
+    buffer_size: usize,
+) -> io::Result<Vec<(PathBuf, BufWriter<File>)>> {
+    (0..FANOUT)
+        .map(|index| {
+            let path = dir.join(format!("{}_{}", prefix, index));
+            let file = File::create(&path)?;
+            Ok((path, BufWriter::with_capacity(buffer_size, file)))
+        })
+        .collect()
+}
+
+/// Flushes and closes bucket files, so that no more than `FANOUT` of them are open at once
+/// while they are counted, and returns their paths
+fn close_buckets(buckets: Vec<(PathBuf, BufWriter<File>)>) -> io::Result<Vec<PathBuf>> {
+    buckets
+        .into_iter()
+        .map(|(path, writer)| {
+            drop(writer.into_inner()?);
+            Ok(path)
+        })
+        .collect()
+}
+
+/// Bucket of a k-mer at a given split depth
+fn bucket_of<K: Kmer>(kmer: &K, depth: usize) -> usize {
+    (kmer_hash(kmer) >> (8 * depth)) as usize % FANOUT
+}
+
+/// Serializes a weighted k-mer into a bucket entry
+fn encode_entry<K: Kmer>(kmer: &K, abundance: f64, entry: &mut [u8]) {
+    kmer.write_bytes(entry);
+    entry[K::BYTES..].copy_from_slice(&abundance.to_le_bytes());
+}
+
+/// Deserializes a bucket entry into a weighted k-mer
+fn decode_entry<K: Kmer>(entry: &[u8]) -> (K, f64) {
+    let abundance = f64::from_le_bytes(entry[K::BYTES..].try_into().unwrap());
+    (K::read_bytes(entry), abundance)
+}
+
+/// Calls `f` on every weighted k-mer of a bucket file, in the order they were written
+fn for_each_entry<K: Kmer>(
+    path: &Path,
+    buffer_size: usize,
+    mut f: impl FnMut(K, f64) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut reader = BufReader::with_capacity(buffer_size, File::open(path)?);
+    let mut entry = vec![0; K::BYTES + 8];
+    loop {
+        match reader.read_exact(&mut entry) {
+            Ok(()) => {
+                let (kmer, abundance) = decode_entry::<K>(&entry);
+                f(kmer, abundance)?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Counts the k-mers of a bucket file into `spectrum`, splitting it first if its
+/// hash map may exceed `max_memory`; the bucket file is removed afterwards
+fn count_bucket<K: Kmer>(
+    path: &Path,
+    depth: usize,
+    aggregation: Aggregation,
+    count_range: &RangeInclusive<f64>,
+    max_memory: usize,
+    buffer_size: usize,
+    spectrum: &mut Spectrum,
+) -> io::Result<()> {
+    // Upper bound: every entry of the bucket is a distinct k-mer
+    let size = fs::metadata(path)?.len();
+    let entries = size as usize / (K::BYTES + 8);
+    let estimated_memory = entries * size_of::<(K, Tally)>() * MAP_OVERHEAD;
+
+    if estimated_memory <= max_memory || depth >= MAX_DEPTH {
+        return count_in_memory::<K>(path, aggregation, count_range, buffer_size, spectrum);
+    }
+
+    let prefix = path.file_name().unwrap().to_string_lossy().into_owned();
+    let mut buckets = create_buckets(path.parent().unwrap(), &prefix, buffer_size)?;
+    let mut entry = vec![0; K::BYTES + 8];
+    for_each_entry::<K>(path, buffer_size, |kmer, abundance| {
+        encode_entry(&kmer, abundance, &mut entry);
+        buckets[bucket_of(&kmer, depth)].1.write_all(&entry)
+    })?;
+    fs::remove_file(path)?;
+
+    for sub_path in close_buckets(buckets)? {
+        if fs::metadata(&sub_path)?.len() == size {
+            // All the entries fell into this bucket, e.g. the occurrences of a single repeated
+            // k-mer: splitting it again would not shrink it, and it has few distinct k-mers
+            count_in_memory::<K>(&sub_path, aggregation, count_range, buffer_size, spectrum)?;
+        } else {
+            count_bucket::<K>(
+                &sub_path,
+                depth + 1,
+                aggregation,
+                count_range,
+                max_memory,
+                buffer_size,
+                spectrum,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Aggregates the k-mers of a bucket file in a hash map, and adds those whose count is in
+/// `count_range` into `spectrum`; the bucket file is removed afterwards
+fn count_in_memory<K: Kmer>(
+    path: &Path,
+    aggregation: Aggregation,
+    count_range: &RangeInclusive<f64>,
+    buffer_size: usize,
+    spectrum: &mut Spectrum,
+) -> io::Result<()> {
+    let mut kmer_counts: HashMap<K, Tally> = HashMap::new();
+    for_each_entry::<K>(path, buffer_size, |kmer, abundance| {
+        aggregation.add(kmer_counts.entry(kmer).or_default(), abundance);
+        Ok(())
+    })?;
+    fs::remove_file(path)?;
+
+    for tally in kmer_counts.values() {
+        let count = aggregation.count(tally);
+        if count_range.contains(&count) {
+            spectrum.add(count, 1);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::count::count_kmers;
+    use bio::io::fasta;
+
+    fn record(id: usize, seq: &[u8], abundance: f64) -> Record {
+        let desc = format!("ka:f:{}", abundance);
+        Record::Fasta(fasta::Record::with_attrs(&id.to_string(), Some(&desc), seq))
+    }
+
+    fn weight(record: &Record) -> Option<f64> {
+        record.desc()?.strip_prefix("ka:f:")?.parse().ok()
+    }
+
+    /// Counts the records on disk with `max_memory` bytes, checks that the spectrum is the
+    /// in-memory one, with and without trimming and a count range, and that no bucket file is
+    /// left behind
+    fn check(records: &[Record], k: usize, max_memory: usize) {
+        for (trim, count_range) in [(0, f64::NEG_INFINITY..=f64::INFINITY), (3, 2.0..=4.0)] {
+            let tmp_dir = tempfile::tempdir().unwrap();
+            let spectrum = count_kmers_on_disk::<u64, _, _>(
+                records.iter().cloned().map(Ok),
+                k,
+                true,
+                trim,
+                weight,
+                Aggregation::Sum,
+                max_memory,
+                tmp_dir.path(),
+                None,
+                &count_range,
+            )
+            .unwrap();
+
+            let counts = count_kmers::<u64, _, _>(
+                records.iter().cloned().map(Ok),
+                k,
+                true,
+                trim,
+                weight,
+                Aggregation::Sum,
+            )
+            .unwrap();
+            let mut expected = Spectrum::new(None);
+            for count in counts.values().filter(|count| count_range.contains(count)) {
+                expected.add(count, 1);
+            }
+            assert_eq!(spectrum, expected);
+            assert_eq!(fs::read_dir(tmp_dir.path()).unwrap().count(), 0);
+        }
+    }
+
+    #[test]
+    fn splits_buckets_over_the_memory_budget() {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let records: Vec<Record> = (0..200)
+            .map(|i| {
+                let seq: Vec<u8> = (0..300)
+                    .map(|_| {
+                        state = state
+                            .wrapping_mul(6364136223846793005)
+                            .wrapping_add(1442695040888963407);
+                        b"ACGT"[(state >> 62) as usize]
+                    })
+                    .collect();
+                record(i, &seq, 1.0 + (i % 7) as f64 / 4.0)
+            })
+            .collect();
+        check(&records, 21, 4096);
+    }
+
+    #[test]
+    fn counts_low_complexity_buckets_without_splitting_them_further() {
+        let poly_a = vec![b'A'; 300_000];
+        let repeat: Vec<u8> = b"ACGTTG".iter().copied().cycle().take(100_000).collect();
+        let records = [
+            record(0, &poly_a, 2.5),
+            record(1, &repeat, 1.0),
+            record(2, &poly_a, 0.5),
+            record(3, b"ACGTACGGTTACGATCGATCGGATCGATCAGGCTAGCTAGGATC", 3.0),
+        ];
+        // Splitting the bucket of the poly-A k-mer again would not shrink it
+        check(&records, 31, 1);
+    }
+}
@@ -0,0 +1,502 @@
+//! Dumps of the k-mer count table, sorted by k-mer, and lookups in binary dumps
+//!
+//! A binary dump starts with a 24-byte header: the magic bytes [`MAGIC`], k as a little-endian
+//! 32-bit integer, 1 if the k-mers are canonical (0 otherwise), 3 reserved bytes and the number
+//! of k-mers as a little-endian 64-bit integer. Each k-mer then takes a fixed-size entry: its
+//! nucleotides packed with 2 bits each in ⌈k/4⌉ big-endian bytes (the last nucleotide in the
+//! least significant bits), followed by its weighted count as a little-endian 64-bit float.
+//! Entries are sorted by k-mer, which is the order of their packed bytes, so that a k-mer is
+//! looked up by binary search without loading the dump.
+
+use clap::ValueEnum;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::count::KmerCounts;
+use crate::kmer::{nucleotide_to_bits, Kmer};
+use crate::spectrum::{Spectrum, MAX_K};
+
+/// Magic bytes starting a binary dump
+pub const MAGIC: &[u8; 8] = b"LKSDUMP1";
+
+/// Size of the header of a binary dump
+const HEADER_SIZE: u64 = 24;
+
+/// Format of a dump of the k-mer counts
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum DumpFormat {
+    /// Sorted fixed-size entries, which the `query` subcommand looks k-mers up in
+    Binary,
+    /// Sorted `KMER\tCOUNT` lines
+    Text,
+}
+
+/// Writes every k-mer and its weighted count, sorted by k-mer
+pub fn write_dump<K: Kmer, W: Write>(
+    output: &mut W,
+    counts: &KmerCounts<K>,
+    k: usize,
+    canonical: bool,
+    format: DumpFormat,
+) -> io::Result<()> {
+    let mut entries: Vec<(K, f64)> = counts.iter().map(|(kmer, count)| (*kmer, count)).collect();
+    entries.sort_unstable_by_key(|&(kmer, _)| kmer);
+
+    if format == DumpFormat::Binary {
+        output.write_all(MAGIC)?;
+        output.write_all(&(k as u32).to_le_bytes())?;
+        output.write_all(&[canonical as u8, 0, 0, 0])?;
+        output.write_all(&(entries.len() as u64).to_le_bytes())?;
+    }
+    let mut bytes = vec![0; K::BYTES];
+    let mut packed = vec![0; packed_size(k)];
+    let mut nucleotides = vec![0; k];
+    for (kmer, count) in entries {
+        // The little-endian bytes of the k-mer hold its nucleotides from the least significant bit
+        kmer.write_bytes(&mut bytes);
+        for (byte, le_byte) in packed
+            .iter_mut()
+            .zip(bytes.iter().rev().skip(K::BYTES - packed_size(k)))
+        {
+            *byte = *le_byte;
+        }
+        match format {
+            DumpFormat::Binary => {
+                output.write_all(&packed)?;
+                output.write_all(&count.to_le_bytes())?;
+            }
+            DumpFormat::Text => {
+                unpack(&packed, &mut nucleotides);
+                output.write_all(&nucleotides)?;
+                writeln!(output, "\t{}", count)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A binary dump, whose k-mers are looked up by binary search in the file
+pub struct KmerDump {
+    path: PathBuf,
+    file: File,
+    k: usize,
+    canonical: bool,
+    len: u64,
+    /// Buffers of the packed k-mer looked up, of its reverse complement and of the entries read
+    query: Vec<u8>,
+    reverse: Vec<u8>,
+    entry: Vec<u8>,
+}
+
+impl KmerDump {
+    /// Opens a binary dump, checking its header and size
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let invalid = |message: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), message),
+            )
+        };
+        let mut file = File::open(path)?;
+        let mut header = [0; HEADER_SIZE as usize];
+        BufReader::new(&mut file)
+            .read_exact(&mut header)
+            .map_err(|_| invalid("not a binary k-mer dump"))?;
+        if &header[..8] != MAGIC {
+            return Err(invalid("not a binary k-mer dump"));
+        }
+        let k = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
+        let canonical = header[12] != 0;
+        let len = u64::from_le_bytes(header[16..24].try_into().unwrap());
+
+        if k == 0 || k > MAX_K {
+            return Err(invalid("corrupted k-mer dump"));
+        }
+
+        let entry_size = packed_size(k) + 8;
+        let size = len
+            .checked_mul(entry_size as u64)
+            .and_then(|size| size.checked_add(HEADER_SIZE));
+        if size != Some(file.metadata()?.len()) {
+            return Err(invalid("truncated or corrupted k-mer dump"));
+        }
+        Ok(KmerDump {
+            path: path.to_path_buf(),
+            file,
+            k,
+            canonical,
+            len,
+            query: vec![0; packed_size(k)],
+            reverse: vec![0; packed_size(k)],
+            entry: vec![0; entry_size],
+        })
+    }
+
+    /// Size of the k-mers
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// Whether the k-mers were counted as canonical
+    pub fn canonical(&self) -> bool {
+        self.canonical
+    }
+
+    /// Number of distinct k-mers
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Whether the dump has no k-mer
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterates over the packed k-mers and their counts, sorted by k-mer, reading the dump again
+    pub fn entries(&self) -> io::Result<DumpEntries> {
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        reader.seek(SeekFrom::Start(HEADER_SIZE))?;
+        Ok(DumpEntries {
+            reader,
+            remaining: self.len,
+            packed_size: packed_size(self.k),
+        })
+    }
+
+    /// Weighted count of a k-mer given as nucleotides (its canonical form if the dump is
+    /// canonical), or `None` if it does not occur or contains a base other than ACGT
+    pub fn get(&mut self, kmer: &[u8]) -> io::Result<Option<f64>> {
+        if kmer.len() != self.k {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "k-mer of size {} looked up in a dump of {}-mers",
+                    kmer.len(),
+                    self.k
+                ),
+            ));
+        }
+        if !pack(kmer, false, &mut self.query) {
+            return Ok(None);
+        }
+        if self.canonical {
+            pack(kmer, true, &mut self.reverse);
+            if self.reverse < self.query {
+                std::mem::swap(&mut self.query, &mut self.reverse);
+            }
+        }
+
+        let (mut low, mut high) = (0, self.len);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            self.file.seek(SeekFrom::Start(
+                HEADER_SIZE + middle * self.entry.len() as u64,
+            ))?;
+            self.file.read_exact(&mut self.entry)?;
+            let (packed, count) = self.entry.split_at(self.query.len());
+            match packed.cmp(&self.query) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => {
+                    return Ok(Some(f64::from_le_bytes(count.try_into().unwrap())))
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Packed k-mers of a binary dump and their counts, sorted by k-mer
+pub struct DumpEntries {
+    reader: BufReader<File>,
+    remaining: u64,
+    packed_size: usize,
+}
+
+impl Iterator for DumpEntries {
+    type Item = io::Result<(Vec<u8>, f64)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let mut packed = vec![0; self.packed_size];
+        let mut count = [0; 8];
+        let result = self
+            .reader
+            .read_exact(&mut packed)
+            .and_then(|_| self.reader.read_exact(&mut count));
+        Some(result.map(|_| (packed, f64::from_le_bytes(count))))
+    }
+}
+
+/// Sums the counts of each k-mer over binary dumps of the same k-mers (size and canonical form),
+/// and returns the spectrum of the sums
+///
+/// The dumps are merged as sorted streams, without loading them in memory.
+pub fn merge_dumps(paths: &[PathBuf], bin_width: Option<f64>) -> io::Result<Spectrum> {
+    let dumps = paths
+        .iter()
+        .map(|path| KmerDump::open(path))
+        .collect::<io::Result<Vec<_>>>()?;
+    if let Some(first) = dumps.first() {
+        if let Some(other) = dumps
+            .iter()
+            .find(|dump| dump.k != first.k || dump.canonical != first.canonical)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} and {} do not have the same k-mer size or canonical form",
+                    first.path.display(),
+                    other.path.display()
+                ),
+            ));
+        }
+    }
+
+    let mut entries = dumps
+        .iter()
+        .map(KmerDump::entries)
+        .collect::<io::Result<Vec<_>>>()?;
+    // Smallest k-mer at the head of each dump, and its count
+    let mut heads = BinaryHeap::new();
+    let mut counts = vec![0.0; dumps.len()];
+    for (i, dump_entries) in entries.iter_mut().enumerate() {
+        if let Some((kmer, count)) = dump_entries.next().transpose()? {
+            heads.push(Reverse((kmer, i)));
+            counts[i] = count;
+        }
+    }
+
+    let mut spectrum = Spectrum::new(bin_width);
+    while let Some(Reverse((kmer, i))) = heads.pop() {
+        let mut sum = 0.0;
+        let mut next = Some(i);
+        while let Some(i) = next {
+            sum += counts[i];
+            if let Some((kmer, count)) = entries[i].next().transpose()? {
+                heads.push(Reverse((kmer, i)));
+                counts[i] = count;
+            }
+            next = match heads.peek() {
+                Some(Reverse((head, _))) if *head == kmer => heads.pop().map(|Reverse((_, j))| j),
+                _ => None,
+            };
+        }
+        spectrum.add(sum, 1);
+    }
+    Ok(spectrum)
+}
+
+/// Number of bytes of a packed k-mer
+fn packed_size(k: usize) -> usize {
+    k.div_ceil(4)
+}
+
+/// Packs the nucleotides of a k-mer, or of its reverse complement, into `out`; returns `false`
+/// if the k-mer contains a base other than ACGT
+fn pack(kmer: &[u8], reverse_complement: bool, out: &mut [u8]) -> bool {
+    out.fill(0);
+    let k = kmer.len();
+    for (i, &n) in kmer.iter().enumerate() {
+        let Some(bits) = nucleotide_to_bits(n) else {
+            return false;
+        };
+        let (bits, i) = if reverse_complement {
+            (3 - bits, k - 1 - i)
+        } else {
+            (bits, i)
+        };
+        let shift = 2 * (k - 1 - i);
+        out[out.len() - 1 - shift / 8] |= (bits as u8) << (shift % 8);
+    }
+    true
+}
+
+/// Unpacks the nucleotides of a packed k-mer into `out`, whose length is k
+fn unpack(packed: &[u8], out: &mut [u8]) {
+    let k = out.len();
+    for (i, n) in out.iter_mut().enumerate() {
+        let shift = 2 * (k - 1 - i);
+        *n = b"ACGT"[((packed[packed.len() - 1 - shift / 8] >> (shift % 8)) & 3) as usize];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abundance::Aggregation;
+    use crate::count::count_kmers;
+    use crate::input::Record;
+    use crate::kmer::{KmerIter, MultiWord};
+    use bio::io::fasta;
+
+    /// Pseudo-random records with abundances that are multiples of 1/4, so that their sums are
+    /// exact whatever their order
+    fn records(seed: u64, count: usize) -> Vec<Record> {
+        let mut state = seed;
+        (0..count)
+            .map(|i| {
+                let seq: Vec<u8> = (0..150 + i % 50)
+                    .map(|_| {
+                        state = state
+                            .wrapping_mul(6364136223846793005)
+                            .wrapping_add(1442695040888963407);
+                        b"ACGT"[(state >> 62) as usize]
+                    })
+                    .collect();
+                let desc = format!("ka:f:{}", 1.0 + (i % 9) as f64 / 4.0);
+                Record::Fasta(fasta::Record::with_attrs(&i.to_string(), Some(&desc), &seq))
+            })
+            .collect()
+    }
+
+    fn counts<K: Kmer>(records: &[Record], k: usize, canonical: bool) -> KmerCounts<K> {
+        let weight = |record: &Record| record.desc()?.strip_prefix("ka:f:")?.parse().ok();
+        let records = records.iter().cloned().map(Ok);
+        count_kmers(records, k, canonical, 0, weight, Aggregation::Sum).unwrap()
+    }
+
+    fn write<K: Kmer>(path: &Path, counts: &KmerCounts<K>, k: usize, canonical: bool) {
+        let mut output = File::create(path).unwrap();
+        write_dump(&mut output, counts, k, canonical, DumpFormat::Binary).unwrap();
+    }
+
+    fn reverse_complement(kmer: &[u8]) -> Vec<u8> {
+        kmer.iter()
+            .rev()
+            .map(|&n| b"TGCA"[nucleotide_to_bits(n).unwrap() as usize])
+            .collect()
+    }
+
+    /// Writes the counts of `k`-mers as a binary dump, reads all its entries back and looks
+    /// up its first, last and absent k-mers
+    fn round_trip<K: Kmer>(k: usize) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dump");
+        let records = records(k as u64, 40);
+        for canonical in [false, true] {
+            let counts = counts::<K>(&records, k, canonical);
+            write(&path, &counts, k, canonical);
+            let mut dump = KmerDump::open(&path).unwrap();
+            assert_eq!(dump.k(), k);
+            assert_eq!(dump.canonical(), canonical);
+            assert_eq!(dump.len(), counts.len() as u64);
+
+            let entries: Vec<(Vec<u8>, f64)> =
+                dump.entries().unwrap().collect::<io::Result<_>>().unwrap();
+            assert!(entries.windows(2).all(|pair| pair[0].0 < pair[1].0));
+            let mut nucleotides = vec![0; k];
+            for (packed, count) in &entries {
+                unpack(packed, &mut nucleotides);
+                let kmer: K = KmerIter::new(&nucleotides, k, false).next().unwrap();
+                assert_eq!(counts.get(&kmer), Some(*count));
+            }
+
+            for (packed, count) in [entries.first().unwrap(), entries.last().unwrap()] {
+                unpack(packed, &mut nucleotides);
+                assert_eq!(dump.get(&nucleotides).unwrap(), Some(*count));
+                let reverse = reverse_complement(&nucleotides);
+                let expected = if canonical {
+                    Some(*count)
+                } else {
+                    let reverse: K = KmerIter::new(&reverse, k, false).next().unwrap();
+                    counts.get(&reverse)
+                };
+                assert_eq!(dump.get(&reverse).unwrap(), expected);
+            }
+
+            // Smaller than the first k-mer, larger than the last one, and in between
+            let absent = [vec![b'A'; k], vec![b'T'; k], b"AC".repeat(k)[..k].to_vec()];
+            for kmer in &absent {
+                let encoded: K = KmerIter::new(kmer, k, canonical).next().unwrap();
+                if counts.get(&encoded).is_none() {
+                    assert_eq!(dump.get(kmer).unwrap(), None);
+                }
+            }
+            let mut invalid = vec![b'A'; k];
+            invalid[k / 2] = b'N';
+            assert_eq!(dump.get(&invalid).unwrap(), None);
+            assert!(dump.get(&vec![b'A'; k + 1]).is_err());
+        }
+    }
+
+    #[test]
+    fn binary_dumps_round_trip() {
+        round_trip::<u64>(21);
+        round_trip::<u64>(32);
+        round_trip::<u128>(45);
+        round_trip::<MultiWord<4>>(101);
+        round_trip::<MultiWord<8>>(130);
+    }
+
+    #[test]
+    fn rejects_truncated_or_corrupted_dumps() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dump");
+        write(&path, &counts::<u64>(&records(5, 3), 21, true), 21, true);
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[..8], MAGIC);
+        std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(KmerDump::open(&path).is_err());
+        std::fs::write(&path, &bytes[..10]).unwrap();
+        assert!(KmerDump::open(&path).is_err());
+
+        // A length overflowing the size of the entries, and a k-mer size out of range
+        let mut corrupted = bytes.clone();
+        corrupted[16..24].copy_from_slice(&u64::MAX.to_le_bytes());
+        std::fs::write(&path, &corrupted).unwrap();
+        assert!(KmerDump::open(&path).is_err());
+        let mut corrupted = bytes;
+        corrupted[8..12].copy_from_slice(&(MAX_K as u32 + 1).to_le_bytes());
+        std::fs::write(&path, &corrupted).unwrap();
+        assert!(KmerDump::open(&path).is_err());
+    }
+
+    #[test]
+    fn text_dumps_are_sorted_by_kmer() {
+        let counts = counts::<u64>(&records(7, 10), 15, true);
+        let mut text = Vec::new();
+        write_dump(&mut text, &counts, 15, true, DumpFormat::Text).unwrap();
+        let lines: Vec<&str> = std::str::from_utf8(&text).unwrap().lines().collect();
+        assert_eq!(lines.len(), counts.len());
+        assert!(lines.windows(2).all(|pair| pair[0] < pair[1]));
+        for line in lines {
+            let (kmer, count) = line.split_once('\t').unwrap();
+            let encoded: u64 = KmerIter::new(kmer.as_bytes(), 15, false).next().unwrap();
+            assert_eq!(counts.get(&encoded), Some(count.parse().unwrap()));
+        }
+    }
+
+    #[test]
+    fn merges_overlapping_dumps() {
+        let dir = tempfile::tempdir().unwrap();
+        let (first, shared, last) = (records(1, 20), records(2, 30), records(3, 10));
+        let paths = [dir.path().join("first"), dir.path().join("second")];
+        let halves = [[&first, &shared], [&shared, &last]];
+        for (path, half) in paths.iter().zip(halves) {
+            let records: Vec<Record> = half.into_iter().flatten().cloned().collect();
+            write(path, &counts::<u128>(&records, 35, true), 35, true);
+        }
+
+        let all: Vec<Record> = [&first, &shared, &shared, &last]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect();
+        let mut expected = Spectrum::new(Some(0.5));
+        for count in counts::<u128>(&all, 35, true).values() {
+            expected.add(count, 1);
+        }
+        assert_eq!(merge_dumps(&paths, Some(0.5)).unwrap(), expected);
+
+        let other = dir.path().join("other");
+        write(&other, &counts::<u128>(&last, 35, false), 35, false);
+        assert!(merge_dumps(&[paths[0].clone(), other], None).is_err());
+    }
+}
@@ -0,0 +1,526 @@
+//! Graph of the unitigs linked in their FASTA headers, and ownership of the junctions they share
+//!
+//! BCALM and Logan unitig headers list the links of each unitig as `L:+:12:-` fields: the end of
+//! the unitig read in the first orientation (`+` forward, `-` reverse complement) overlaps by
+//! k-1 bases, k being the assembly k, the start of unitig `12` read in the second orientation.
+//! All the unitigs meeting at such a junction share its (k-1)-mer, whose k-mers are then counted
+//! once per unitig. Each junction is owned by the unitig of smallest id meeting at it, so that the
+//! others can drop its k-mers; a unitig meeting a junction at both ends, as along a self-loop,
+//! keeps it at its start only.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use crate::abundance::{AbundanceSource, HeaderAbundance};
+use crate::input::Record;
+
+/// A link from the end of a unitig to the start of another one, as given in its header
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Link {
+    /// Orientation of the unitig whose end is linked: its end if forward, its start otherwise
+    pub forward: bool,
+    /// Identifier of the linked unitig
+    pub target: u64,
+    /// Orientation of the linked unitig whose start is linked
+    pub target_forward: bool,
+}
+
+/// Links of the `L:+:12:-` fields of a header
+pub fn parse_links(header: &str) -> impl Iterator<Item = Link> + '_ {
+    let strand = |sign: &str| match sign {
+        "+" => Some(true),
+        "-" => Some(false),
+        _ => None,
+    };
+    header.split_ascii_whitespace().filter_map(move |field| {
+        let mut parts = field.strip_prefix("L:")?.split(':');
+        let forward = strand(parts.next()?)?;
+        let target = parts.next()?.parse().ok()?;
+        let target_forward = strand(parts.next()?)?;
+        match parts.next() {
+            None => Some(Link {
+                forward,
+                target,
+                target_forward,
+            }),
+            Some(_) => None,
+        }
+    })
+}
+
+/// Identifier of the unitig of a record, which links refer to: the record identifier if it is a
+/// number (BCALM), or the number after its last `_` (Logan, e.g. `SRR5221391_12`)
+pub fn node_id(id: &str) -> Option<u64> {
+    id.rsplit('_').next()?.parse().ok()
+}
+
+/// Unitigs and the links given in their headers
+#[derive(Clone, Debug, Default)]
+pub struct LinkGraph {
+    links: HashMap<u64, Vec<Link>>,
+}
+
+impl LinkGraph {
+    /// An empty graph
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the links of the headers of the given records; records whose identifier is not a
+    /// unitig one are ignored
+    pub fn from_records<I>(records: I) -> io::Result<Self>
+    where
+        I: Iterator<Item = io::Result<Record>>,
+    {
+        let mut graph = Self::new();
+        for record in records {
+            let record = record?;
+            if let Some(node) = node_id(record.id()) {
+                graph.add(node, parse_links(record.desc().unwrap_or_default()));
+            }
+        }
+        Ok(graph)
+    }
+
+    /// Adds a unitig and its links
+    pub fn add(&mut self, node: u64, links: impl IntoIterator<Item = Link>) {
+        self.links.entry(node).or_default().extend(links);
+    }
+
+    /// Number of unitigs
+    pub fn len(&self) -> usize {
+        self.links.len()
+    }
+
+    /// Whether the graph has no unitig
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    /// Links of a unitig
+    pub fn links(&self, node: u64) -> &[Link] {
+        self.links.get(&node).map_or(&[], Vec::as_slice)
+    }
+
+    /// Links of the end (`forward`) or of the start of a unitig
+    fn side(&self, node: u64, forward: bool) -> impl Iterator<Item = &Link> {
+        self.links(node)
+            .iter()
+            .filter(move |link| link.forward == forward)
+    }
+
+    /// Unitig side owning the junction of the end (`forward`) or of the start of a unitig: the
+    /// smallest unitig identifier meeting at it, and whether it meets it by its end (`true`) or
+    /// by its start (`false`, preferred); `None` if that side has no link
+    pub fn junction_owner(&self, node: u64, forward: bool) -> Option<(u64, bool)> {
+        // A link reaches the start of its target in the target orientation, i.e. its end if
+        // reverse; the sides meeting at the junction are the ones linked to the sides it reaches
+        let reached = |link: &Link| (link.target, !link.target_forward);
+        self.side(node, forward)
+            .flat_map(|link| {
+                let (target, target_side) = reached(link);
+                self.side(target, target_side)
+                    .map(reached)
+                    .chain([(node, forward), (target, target_side)])
+            })
+            .min()
+    }
+
+    /// Whether a unitig keeps the k-mers of the junctions at its start and at its end: it does
+    /// unless another unitig of smaller identifier meets it there, or it meets the junction of
+    /// its end at its start too
+    pub fn owns_junctions(&self, node: u64) -> (bool, bool) {
+        let owns = |forward| {
+            self.junction_owner(node, forward)
+                .is_none_or(|owner| owner == (node, forward))
+        };
+        (owns(false), owns(true))
+    }
+
+    /// Statistics of the graph
+    pub fn stats(&self) -> GraphStats {
+        let mut stats = GraphStats {
+            unitigs: self.len() as u64,
+            ..Default::default()
+        };
+        let mut links = HashSet::new();
+        // Unitigs by the targets of the links of their two sides
+        let mut paths: HashMap<[Vec<(u64, bool)>; 2], u64> = HashMap::new();
+        for (&node, node_links) in &self.links {
+            *stats.degrees.entry(node_links.len()).or_default() += 1;
+            for link in node_links {
+                // A link is listed by both of its unitigs, from opposite orientations
+                let mut ends = [(node, link.forward), (link.target, !link.target_forward)];
+                ends.sort_unstable();
+                links.insert(ends);
+            }
+
+            let mut sides = [false, true].map(|forward| {
+                let mut targets: Vec<_> = self
+                    .side(node, forward)
+                    .map(|link| (link.target, link.target_forward))
+                    .collect();
+                targets.sort_unstable();
+                targets
+            });
+            match (sides[0].is_empty(), sides[1].is_empty()) {
+                (true, true) => stats.isolated += 1,
+                (true, false) | (false, true) => stats.tips += 1,
+                (false, false) => {
+                    sides.sort();
+                    *paths.entry(sides).or_default() += 1;
+                }
+            }
+        }
+        stats.links = links.len() as u64;
+        stats.bubbles = paths.values().filter(|&&unitigs| unitigs > 1).count() as u64;
+        stats
+    }
+}
+
+/// Statistics of the graph of the unitigs linked in their headers
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GraphStats {
+    /// Unitigs of the graph
+    pub unitigs: u64,
+    /// Links between unitigs, each one being listed in the headers of both of its unitigs
+    pub links: u64,
+    /// Unitigs without link
+    pub isolated: u64,
+    /// Unitigs linked at one end only
+    pub tips: u64,
+    /// Groups of unitigs linked to the same unitigs at both ends, such as the alleles of a SNP
+    pub bubbles: u64,
+    /// Number of unitigs by number of links
+    pub degrees: BTreeMap<usize, u64>,
+}
+
+impl GraphStats {
+    /// Adds the statistics of another graph, such as the one of another input
+    pub fn merge(&mut self, other: &GraphStats) {
+        self.unitigs += other.unitigs;
+        self.links += other.links;
+        self.isolated += other.isolated;
+        self.tips += other.tips;
+        self.bubbles += other.bubbles;
+        for (&degree, &unitigs) in &other.degrees {
+            *self.degrees.entry(degree).or_default() += unitigs;
+        }
+    }
+}
+
+impl fmt::Display for GraphStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Unitigs: {}", self.unitigs)?;
+        writeln!(f, "Links: {}", self.links)?;
+        writeln!(f, "Isolated unitigs: {}", self.isolated)?;
+        writeln!(f, "Tips: {}", self.tips)?;
+        writeln!(f, "Bubbles: {}", self.bubbles)?;
+        write!(f, "Unitigs by degree:")?;
+        for (degree, unitigs) in &self.degrees {
+            write!(f, " {}:{}", degree, unitigs)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes unitigs and the links of their headers as a GFA1 graph
+///
+/// Each record becomes an `S` segment with its length (`LN`), and if its header has one, its
+/// abundance (`ka`) and k-mer count (`KC`: the abundance times its number of assembly k-mers,
+/// which Bandage reads as depth). Segments are named after the unitig identifier of their
+/// records (see [`node_id`]), or after the whole identifier if it has none. Records whose
+/// abundance is out of `abundance_range` are skipped, as well as the ones without abundance
+/// if the range is bounded. Each link between two written segments becomes an `L` line, with
+/// an overlap of the assembly k minus 1 bases.
+pub fn write_gfa<W, I>(
+    output: &mut W,
+    records: I,
+    abundance: &AbundanceSource,
+    assembly_k: usize,
+    abundance_range: &RangeInclusive<f64>,
+) -> io::Result<()>
+where
+    W: Write,
+    I: Iterator<Item = io::Result<Record>>,
+{
+    let bounded = abundance_range.start().is_finite() || abundance_range.end().is_finite();
+    let sign = |forward| if forward { '+' } else { '-' };
+    writeln!(output, "H\tVN:Z:1.0")?;
+
+    let mut segments = HashSet::new();
+    let mut links = Vec::new();
+    for record in records {
+        let record = record?;
+        let abundance = match abundance.abundance(&record) {
+            HeaderAbundance::Found(abundance) => Some(abundance),
+            _ => None,
+        };
+        if abundance.map_or(bounded, |abundance| !abundance_range.contains(&abundance)) {
+            continue;
+        }
+
+        let node = node_id(record.id());
+        match node {
+            Some(node) => write!(output, "S\t{}\t", node)?,
+            None => write!(output, "S\t{}\t", record.id())?,
+        }
+        output.write_all(record.seq())?;
+        write!(output, "\tLN:i:{}", record.seq().len())?;
+        if let Some(abundance) = abundance {
+            let kmers = (record.seq().len() + 1).saturating_sub(assembly_k);
+            let kmer_count = (abundance * kmers as f64).round() as u64;
+            write!(output, "\tKC:i:{}\tka:f:{}", kmer_count, abundance)?;
+        }
+        writeln!(output)?;
+
+        if let Some(node) = node {
+            segments.insert(node);
+            let header = record.desc().unwrap_or_default();
+            links.extend(parse_links(header).map(|link| (node, link)));
+        }
+    }
+
+    for (node, link) in links {
+        // A link is listed by both of its unitigs, from opposite orientations: write it once
+        let mirror = (link.target, !link.target_forward);
+        if (node, link.forward) <= mirror && segments.contains(&link.target) {
+            writeln!(
+                output,
+                "L\t{}\t{}\t{}\t{}\t{}M",
+                node,
+                sign(link.forward),
+                link.target,
+                sign(link.target_forward),
+                assembly_k.saturating_sub(1)
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abundance::Aggregation;
+    use crate::spectrum::{Spectrum, SpectrumBuilder};
+    use crate::stats::RunStats;
+    use bio::io::fasta;
+    use std::fs::File;
+
+    /// Assembly k of the test graph
+    const ASSEMBLY_K: usize = 21;
+
+    fn random_bases(state: &mut u64, len: usize) -> String {
+        (0..len)
+            .map(|_| {
+                *state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                b"ACGT"[(*state >> 62) as usize] as char
+            })
+            .collect()
+    }
+
+    fn reverse_complement(seq: &str) -> String {
+        seq.bytes()
+            .rev()
+            .map(|n| match n {
+                b'A' => 'T',
+                b'C' => 'G',
+                b'G' => 'C',
+                _ => 'A',
+            })
+            .collect()
+    }
+
+    /// A BCALM graph sharing three junctions, (k-1)-mers `J1`, `J2` and `J3`:
+    ///
+    /// - 1 ends with `J1`, and 2 and 3 start with it (the two paths of a bubble);
+    /// - 2 and 3 end with `J2`, as does 4, stored in reverse complement;
+    /// - 4 starts with the reverse complement of `J3`, and 5 starts and ends with `J3`
+    ///   (a self-loop);
+    /// - 6 has no link;
+    /// - 7 starts and ends with `J4`, and is only linked to itself.
+    ///
+    /// As in a compacted de Bruijn graph, the bases next to a shared junction differ.
+    fn unitigs() -> Vec<(u64, String, &'static str)> {
+        let mut state = 0x2545_f491_4f6c_dd1d;
+        let mut random = |len| random_bases(&mut state, len);
+        let (j1, j2, j3, j4) = (random(20), random(20), random(20), random(20));
+        vec![
+            (1, random(30) + &j1, "L:+:2:+ L:+:3:+"),
+            (2, j1.clone() + "A" + &j2, "L:-:1:- L:+:4:-"),
+            (3, j1.clone() + "C" + &j2, "L:-:1:- L:+:4:-"),
+            (
+                4,
+                reverse_complement(&(j2.clone() + &random(29) + "A" + &j3)),
+                "L:+:2:- L:+:3:- L:-:5:+",
+            ),
+            (
+                5,
+                j3.clone() + &random(24) + "C" + &j3,
+                "L:+:5:+ L:-:4:+ L:-:5:-",
+            ),
+            (6, random(40), ""),
+            (7, j4.clone() + &random(30) + &j4, "L:+:7:+ L:-:7:-"),
+        ]
+    }
+
+    fn graph() -> LinkGraph {
+        let mut graph = LinkGraph::new();
+        for (node, _, links) in unitigs() {
+            graph.add(node, parse_links(links));
+        }
+        graph
+    }
+
+    #[test]
+    fn parses_links() {
+        let links: Vec<Link> = parse_links("ka:f:2.5 L:+:12:- L:-:3:+ L:+:x:+ L:+:1:+:2").collect();
+        assert_eq!(
+            links,
+            [
+                Link {
+                    forward: true,
+                    target: 12,
+                    target_forward: false
+                },
+                Link {
+                    forward: false,
+                    target: 3,
+                    target_forward: true
+                },
+            ]
+        );
+        assert_eq!(node_id("SRR5221391_12"), Some(12));
+        assert_eq!(node_id("12"), Some(12));
+        assert_eq!(node_id("contig"), None);
+    }
+
+    #[test]
+    fn each_junction_has_one_owner() {
+        let graph = graph();
+        assert_eq!(graph.owns_junctions(1), (true, true));
+        assert_eq!(graph.owns_junctions(2), (false, true));
+        assert_eq!(graph.owns_junctions(3), (false, false));
+        assert_eq!(graph.owns_junctions(4), (true, false));
+        // The self-loop meets the junction of 4 at both ends
+        assert_eq!(graph.junction_owner(5, true), Some((4, false)));
+        assert_eq!(graph.owns_junctions(5), (false, false));
+        assert_eq!(graph.owns_junctions(6), (true, true));
+        assert_eq!(graph.owns_junctions(7), (true, false));
+    }
+
+    #[test]
+    fn graph_stats() {
+        let stats = graph().stats();
+        assert_eq!(
+            stats,
+            GraphStats {
+                unitigs: 7,
+                links: 7,
+                isolated: 1,
+                tips: 1,
+                bubbles: 1,
+                degrees: BTreeMap::from([(0, 1), (2, 4), (3, 2)]),
+            }
+        );
+        let mut merged = stats.clone();
+        merged.merge(&stats);
+        assert_eq!(merged.links, 14);
+        assert_eq!(merged.degrees[&2], 8);
+    }
+
+    #[test]
+    fn counts_the_kmers_of_each_junction_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("unitigs.fa");
+        let mut writer = fasta::Writer::new(File::create(&path).unwrap());
+        for (node, seq, links) in unitigs() {
+            let desc = format!("ka:f:1.0 {}", links);
+            writer
+                .write(&node.to_string(), Some(&desc), seq.as_bytes())
+                .unwrap();
+        }
+        drop(writer);
+
+        let k = 15;
+        let builder = SpectrumBuilder::new(k)
+            .canonical(true)
+            .assembly_k(ASSEMBLY_K)
+            .aggregation(Aggregation::Unweighted)
+            .graph_stats(true);
+        // The random sequences have no repeated 15-mer besides the junctions
+        let distinct = builder.build_from_paths(&[&path]).unwrap();
+        assert!(distinct.iter().any(|(count, _)| count > 1.0));
+
+        let (spectrum, stats): (Spectrum, RunStats) = builder
+            .clone()
+            .dedup_junctions(true)
+            .build_from_paths_with_stats(&[&path])
+            .unwrap();
+        let mut expected = Spectrum::new(None);
+        expected.add(1.0, distinct.distinct_kmers());
+        assert_eq!(spectrum, expected);
+        assert_eq!(stats.graph, Some(graph().stats()));
+
+        // Linked unitigs may be read in opposite orientations
+        let error = builder
+            .canonical(false)
+            .dedup_junctions(true)
+            .build_from_paths(&[&path])
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    /// GFA graph of three linked unitigs, listing each link from both of its ends, and of a
+    /// contig without unitig identifier nor abundance, keeping the abundances in `range`
+    fn gfa(range: RangeInclusive<f64>) -> String {
+        let records = [
+            ("SRR1_1", Some("ka:f:2 L:+:2:- L:-:3:+"), "ACGTACGTAC"),
+            ("SRR1_2", Some("ka:f:4.5 L:+:1:-"), "TTGCATGC"),
+            ("SRR1_3", Some("ka:f:0.5 L:-:1:+"), "GGATCC"),
+            ("contig", None, "ACGT"),
+        ]
+        .map(|(id, desc, seq)| {
+            Ok(Record::Fasta(fasta::Record::with_attrs(
+                id,
+                desc,
+                seq.as_bytes(),
+            )))
+        });
+        let mut output = Vec::new();
+        let abundance = AbundanceSource::default();
+        write_gfa(&mut output, records.into_iter(), &abundance, 5, &range).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn writes_segments_and_links_once() {
+        assert_eq!(
+            gfa(f64::NEG_INFINITY..=f64::INFINITY),
+            "H\tVN:Z:1.0\n\
+             S\t1\tACGTACGTAC\tLN:i:10\tKC:i:12\tka:f:2\n\
+             S\t2\tTTGCATGC\tLN:i:8\tKC:i:18\tka:f:4.5\n\
+             S\t3\tGGATCC\tLN:i:6\tKC:i:1\tka:f:0.5\n\
+             S\tcontig\tACGT\tLN:i:4\n\
+             L\t1\t+\t2\t-\t4M\n\
+             L\t1\t-\t3\t+\t4M\n"
+        );
+    }
+
+    #[test]
+    fn drops_the_links_of_filtered_segments() {
+        // Records without abundance are skipped as soon as the range is bounded
+        assert_eq!(
+            gfa(1.0..=f64::INFINITY),
+            "H\tVN:Z:1.0\n\
+             S\t1\tACGTACGTAC\tLN:i:10\tKC:i:12\tka:f:2\n\
+             S\t2\tTTGCATGC\tLN:i:8\tKC:i:18\tka:f:4.5\n\
+             L\t1\t+\t2\t-\t4M\n"
+        );
+    }
+}
@@ -0,0 +1,513 @@
+//! Structured Logan headers, and filters on their columns
+//!
+//! A Logan header such as `>ERR1255802_25102 ka:f:7.304 L:+:11613034:+ 1 0.08019` holds the
+//! accession of the run and the counter of the sequence in its identifier, then the abundance,
+//! the links to other unitigs (see [`crate::graph`]) and trailing numeric fields.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::graph::{parse_links, Link};
+use crate::input::Record;
+use crate::spectrum::width_decimals;
+
+/// Columns of a Logan header
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LoganHeader {
+    /// Accession of the run, before the last `_` of the identifier (the whole identifier if it
+    /// has none)
+    pub accession: String,
+    /// Counter of the sequence in the run, after the last `_` of the identifier
+    pub counter: Option<u64>,
+    /// Abundance of the `ka:f:` field
+    pub abundance: Option<f64>,
+    /// Links of the `L:+:12:-` fields
+    pub links: Vec<Link>,
+    /// Numeric fields following the abundance and the links
+    pub fields: Vec<f64>,
+}
+
+impl LoganHeader {
+    /// Parses the identifier and the description of a header; fields that are neither the
+    /// abundance, a link or a number are ignored
+    pub fn parse(id: &str, desc: Option<&str>) -> Self {
+        let (accession, counter) = match id.rsplit_once('_') {
+            Some((accession, counter)) => match counter.parse() {
+                Ok(counter) => (accession, Some(counter)),
+                Err(_) => (id, None),
+            },
+            None => (id, None),
+        };
+        let mut header = LoganHeader {
+            accession: accession.to_string(),
+            counter,
+            ..Default::default()
+        };
+        for field in desc.unwrap_or_default().split_ascii_whitespace() {
+            if let Some(abundance) = field.strip_prefix("ka:f:") {
+                header.abundance = abundance.parse().ok();
+            } else if field.starts_with("L:") {
+                header.links.extend(parse_links(field));
+            } else if let Ok(number) = field.parse() {
+                header.fields.push(number);
+            }
+        }
+        header
+    }
+
+    /// Parses the header of a record
+    pub fn from_record(record: &Record) -> Self {
+        Self::parse(record.id(), record.desc())
+    }
+
+    /// Numeric value of a column, or `None` for the accession and for missing columns
+    pub fn number(&self, column: HeaderColumn) -> Option<f64> {
+        match column {
+            HeaderColumn::Accession => None,
+            HeaderColumn::Counter => self.counter.map(|counter| counter as f64),
+            HeaderColumn::Abundance => self.abundance,
+            HeaderColumn::Links => Some(self.links.len() as f64),
+            HeaderColumn::Field(n) => self.fields.get(n - 1).copied(),
+        }
+    }
+
+    /// Value of a column as text, or `None` if it is missing
+    pub fn value(&self, column: HeaderColumn) -> Option<String> {
+        match column {
+            HeaderColumn::Accession => Some(self.accession.clone()),
+            _ => self.number(column).map(|number| number.to_string()),
+        }
+    }
+}
+
+/// A column of Logan headers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeaderColumn {
+    /// `accession`: accession of the run
+    Accession,
+    /// `counter`: counter of the sequence in the run
+    Counter,
+    /// `ka`: abundance
+    Abundance,
+    /// `links`: number of links
+    Links,
+    /// `fN`: N-th trailing numeric field, from 1
+    Field(usize),
+}
+
+impl FromStr for HeaderColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "accession" => Ok(HeaderColumn::Accession),
+            "counter" => Ok(HeaderColumn::Counter),
+            "ka" => Ok(HeaderColumn::Abundance),
+            "links" => Ok(HeaderColumn::Links),
+            _ => match s.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                Some(n) if n > 0 => Ok(HeaderColumn::Field(n)),
+                _ => Err(format!(
+                    "unknown header column `{}`: expected accession, counter, ka, links or f1, f2...",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for HeaderColumn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderColumn::Accession => write!(f, "accession"),
+            HeaderColumn::Counter => write!(f, "counter"),
+            HeaderColumn::Abundance => write!(f, "ka"),
+            HeaderColumn::Links => write!(f, "links"),
+            HeaderColumn::Field(n) => write!(f, "f{}", n),
+        }
+    }
+}
+
+/// Comparison operator of a header filter
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operator {
+    /// `<`
+    Less,
+    /// `<=`
+    LessOrEqual,
+    /// `>`
+    Greater,
+    /// `>=`
+    GreaterOrEqual,
+    /// `==` or `=`
+    Equal,
+    /// `!=`
+    NotEqual,
+}
+
+impl Operator {
+    /// Operators and their symbols, the longest ones first so that they are matched first
+    const SYMBOLS: [(&'static str, Operator); 7] = [
+        ("<=", Operator::LessOrEqual),
+        (">=", Operator::GreaterOrEqual),
+        ("==", Operator::Equal),
+        ("!=", Operator::NotEqual),
+        ("<", Operator::Less),
+        (">", Operator::Greater),
+        ("=", Operator::Equal),
+    ];
+
+    /// Whether `a` compares to `b` with the operator
+    fn compare<T: PartialOrd + ?Sized>(self, a: &T, b: &T) -> bool {
+        match self {
+            Operator::Less => a < b,
+            Operator::LessOrEqual => a <= b,
+            Operator::Greater => a > b,
+            Operator::GreaterOrEqual => a >= b,
+            Operator::Equal => a == b,
+            Operator::NotEqual => a != b,
+        }
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (symbol, _) = Self::SYMBOLS
+            .iter()
+            .find(|(_, operator)| operator == self)
+            .unwrap();
+        write!(f, "{}", symbol)
+    }
+}
+
+/// A condition on a column of Logan headers, such as `f2<0.1` or `accession==ERR1255802`
+///
+/// Records whose header does not have the column do not satisfy it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeaderFilter {
+    column: HeaderColumn,
+    operator: Operator,
+    value: String,
+    number: f64,
+}
+
+impl HeaderFilter {
+    /// A condition comparing a column to a value, which must be a number unless the column is
+    /// the accession, only compared with `==` and `!=`
+    pub fn new(column: HeaderColumn, operator: Operator, value: &str) -> Result<Self, String> {
+        let number = match column {
+            HeaderColumn::Accession => match operator {
+                Operator::Equal | Operator::NotEqual => f64::NAN,
+                _ => return Err("accessions can only be compared with == and !=".to_string()),
+            },
+            _ => value
+                .parse()
+                .map_err(|_| format!("invalid number `{}` for column {}", value, column))?,
+        };
+        Ok(HeaderFilter {
+            column,
+            operator,
+            value: value.to_string(),
+            number,
+        })
+    }
+
+    /// Column of the condition
+    pub fn column(&self) -> HeaderColumn {
+        self.column
+    }
+
+    /// Whether a header satisfies the condition
+    pub fn matches(&self, header: &LoganHeader) -> bool {
+        match self.column {
+            HeaderColumn::Accession => self
+                .operator
+                .compare(header.accession.as_str(), self.value.as_str()),
+            column => header
+                .number(column)
+                .is_some_and(|number| self.operator.compare(&number, &self.number)),
+        }
+    }
+}
+
+impl FromStr for HeaderFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (position, symbol, operator) = Operator::SYMBOLS
+            .iter()
+            .filter_map(|&(symbol, operator)| Some((s.find(symbol)?, symbol, operator)))
+            .min_by_key(|&(position, symbol, _)| (position, usize::MAX - symbol.len()))
+            .ok_or_else(|| format!("no comparison operator (<, <=, >, >=, ==, !=) in `{}`", s))?;
+        let column = s[..position].trim().parse()?;
+        HeaderFilter::new(column, operator, s[position + symbol.len()..].trim())
+    }
+}
+
+impl fmt::Display for HeaderFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}{}", self.column, self.operator, self.value)
+    }
+}
+
+/// Grouping of records by a column of their Logan header
+///
+/// Records are grouped by the value of the column, or into bins of a given width of it for
+/// numeric columns. The abundance and the counter take about one value per record, so they are
+/// only grouped into bins, and other numeric values must be integers unless binned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HeaderGroups {
+    column: HeaderColumn,
+    width: Option<f64>,
+}
+
+/// Group of a record: its accession, or the value of a numeric column, or its bin, as an integer
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HeaderGroup {
+    /// Accession of the run
+    Accession(String),
+    /// Value of a numeric column, or index of its bin
+    Number(i64),
+}
+
+impl HeaderGroups {
+    /// Groups records by the value of a column, or into bins of `width` of it if given
+    pub fn new(column: HeaderColumn, width: Option<f64>) -> Result<Self, String> {
+        match (column, width) {
+            (_, Some(width)) if !width.is_finite() || width <= 0.0 => {
+                Err("group width must be strictly positive".to_string())
+            }
+            (HeaderColumn::Accession, Some(_)) => {
+                Err("accessions cannot be grouped into bins".to_string())
+            }
+            (HeaderColumn::Counter | HeaderColumn::Abundance, None) => Err(format!(
+                "{} takes about one value per record, and can only be grouped into bins of a given width",
+                column
+            )),
+            _ => Ok(HeaderGroups { column, width }),
+        }
+    }
+
+    /// Column grouping the records
+    pub fn column(&self) -> HeaderColumn {
+        self.column
+    }
+
+    /// Group of a header, or `None` if it does not have the column; fails on values that are
+    /// not integers, unless they are binned
+    pub fn group(&self, header: &LoganHeader) -> Result<Option<HeaderGroup>, String> {
+        let number = match self.column {
+            HeaderColumn::Accession => {
+                return Ok(Some(HeaderGroup::Accession(header.accession.clone())))
+            }
+            column => match header.number(column) {
+                Some(number) => number,
+                None => return Ok(None),
+            },
+        };
+        let number = match self.width {
+            Some(width) => (number / width).floor(),
+            None if number.fract() == 0.0 => number,
+            None => {
+                return Err(format!(
+                    "fractional {} value {}, which can only be grouped into bins of a given width,",
+                    self.column, number
+                ))
+            }
+        };
+        Ok(Some(HeaderGroup::Number(number as i64)))
+    }
+
+    /// Name of a group: the accession, or the column followed by the value or the lower bound
+    /// of the bin, such as `links_2` or `ka_2.5`
+    pub fn name(&self, group: &HeaderGroup) -> String {
+        match group {
+            HeaderGroup::Accession(accession) => accession.clone(),
+            HeaderGroup::Number(number) => match self.width {
+                Some(width) => format!(
+                    "{}_{:.*}",
+                    self.column,
+                    width_decimals(width),
+                    *number as f64 * width
+                ),
+                None => format!("{}_{}", self.column, number),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: (&str, &str) = (
+        "ERR1255802_25102",
+        "ka:f:7.304 L:+:11613034:+ L:-:12:- 1 0.08019",
+    );
+
+    fn header() -> LoganHeader {
+        LoganHeader::parse(HEADER.0, Some(HEADER.1))
+    }
+
+    #[test]
+    fn parses_headers_with_links_and_trailing_fields() {
+        let header = header();
+        assert_eq!(header.accession, "ERR1255802");
+        assert_eq!(header.counter, Some(25102));
+        assert_eq!(header.abundance, Some(7.304));
+        assert_eq!(
+            header.links,
+            [
+                Link {
+                    forward: true,
+                    target: 11613034,
+                    target_forward: true
+                },
+                Link {
+                    forward: false,
+                    target: 12,
+                    target_forward: false
+                }
+            ]
+        );
+        assert_eq!(header.fields, [1.0, 0.08019]);
+        assert_eq!(header.number(HeaderColumn::Links), Some(2.0));
+        assert_eq!(header.number(HeaderColumn::Field(2)), Some(0.08019));
+        assert_eq!(header.number(HeaderColumn::Field(3)), None);
+        assert_eq!(header.number(HeaderColumn::Accession), None);
+        assert_eq!(
+            header.value(HeaderColumn::Accession).as_deref(),
+            Some("ERR1255802")
+        );
+        assert_eq!(header.value(HeaderColumn::Field(1)).as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn parses_headers_without_links_nor_trailing_fields() {
+        let header = LoganHeader::parse("SRR1_7", Some("ka:f:2"));
+        assert_eq!(header.abundance, Some(2.0));
+        assert!(header.links.is_empty() && header.fields.is_empty());
+        assert_eq!(header.number(HeaderColumn::Links), Some(0.0));
+
+        let header = LoganHeader::parse("SRR1_7", None);
+        assert_eq!(
+            (header.accession.as_str(), header.counter),
+            ("SRR1", Some(7))
+        );
+        assert_eq!(header.abundance, None);
+        assert_eq!(header.value(HeaderColumn::Abundance), None);
+
+        // Identifiers without a numeric counter are accessions as a whole
+        for id in ["ERR1255802", "contig_x", "a_b_"] {
+            let header = LoganHeader::parse(id, None);
+            assert_eq!((header.accession.as_str(), header.counter), (id, None));
+        }
+        assert_eq!(LoganHeader::parse("a_b_3", None).accession, "a_b");
+    }
+
+    #[test]
+    fn leaves_out_malformed_numbers() {
+        let header = LoganHeader::parse("SRR1_x2", Some("ka:f:x 1 abc L:+:x:+ L:+:3 2.5e1 -4"));
+        assert_eq!(header.counter, None);
+        assert_eq!(header.abundance, None);
+        assert!(header.links.is_empty());
+        assert_eq!(header.fields, [1.0, 25.0, -4.0]);
+    }
+
+    #[test]
+    fn parses_and_prints_columns() {
+        for name in ["accession", "counter", "ka", "links", "f1", "f12"] {
+            let column: HeaderColumn = name.parse().unwrap();
+            assert_eq!(column.to_string(), name);
+        }
+        assert_eq!("f3".parse(), Ok(HeaderColumn::Field(3)));
+        for name in ["f0", "f", "fx", "f-1", "length", "KA", ""] {
+            assert!(name.parse::<HeaderColumn>().is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn filters_with_each_operator() {
+        let header = header();
+        for (filter, operator, expected) in [
+            ("ka<7.304", Operator::Less, false),
+            ("ka<=7.304", Operator::LessOrEqual, true),
+            ("ka>7", Operator::Greater, true),
+            ("ka>=8", Operator::GreaterOrEqual, false),
+            ("f1==1", Operator::Equal, true),
+            ("f1 = 1", Operator::Equal, true),
+            ("f2!=0.08019", Operator::NotEqual, false),
+            ("counter>25101", Operator::Greater, true),
+            ("links<=1", Operator::LessOrEqual, false),
+            ("accession==ERR1255802", Operator::Equal, true),
+            ("accession!=ERR1255802", Operator::NotEqual, false),
+        ] {
+            let parsed: HeaderFilter = filter.parse().unwrap();
+            assert_eq!(parsed.operator, operator, "{}", filter);
+            assert_eq!(parsed.matches(&header), expected, "{}", filter);
+        }
+        let filter: HeaderFilter = " f2 >= 0.1 ".parse().unwrap();
+        assert_eq!(filter.to_string(), "f2>=0.1");
+        assert_eq!(filter.to_string().parse(), Ok(filter));
+    }
+
+    #[test]
+    fn rejects_malformed_filters() {
+        for filter in [
+            "f1<x",
+            "ka>",
+            "f1",
+            "f0<1",
+            "size>1",
+            "accession<ERR1",
+            "accession>=ERR1",
+        ] {
+            assert!(filter.parse::<HeaderFilter>().is_err(), "{}", filter);
+        }
+    }
+
+    #[test]
+    fn headers_without_the_column_do_not_match() {
+        let header = LoganHeader::parse("ERR1255802", None);
+        for filter in ["ka>0", "ka!=1", "counter>=0", "f1!=0", "f1==0"] {
+            let filter: HeaderFilter = filter.parse().unwrap();
+            assert!(!filter.matches(&header), "{}", filter);
+        }
+    }
+
+    #[test]
+    fn groups_by_value_or_bin() {
+        let header = header();
+        let group = |column, width| {
+            let groups = HeaderGroups::new(column, width).unwrap();
+            let group = groups.group(&header).unwrap();
+            group.map(|group| groups.name(&group))
+        };
+        assert_eq!(
+            group(HeaderColumn::Accession, None).as_deref(),
+            Some("ERR1255802")
+        );
+        assert_eq!(group(HeaderColumn::Links, None).as_deref(), Some("links_2"));
+        assert_eq!(group(HeaderColumn::Field(1), None).as_deref(), Some("f1_1"));
+        assert_eq!(group(HeaderColumn::Field(3), None), None);
+        assert_eq!(
+            group(HeaderColumn::Abundance, Some(2.5)).as_deref(),
+            Some("ka_5.0")
+        );
+        assert_eq!(
+            group(HeaderColumn::Counter, Some(1000.0)).as_deref(),
+            Some("counter_25000")
+        );
+        assert_eq!(
+            group(HeaderColumn::Field(2), Some(0.05)).as_deref(),
+            Some("f2_0.05")
+        );
+
+        // Continuous values are only grouped into bins
+        let groups = HeaderGroups::new(HeaderColumn::Field(2), None).unwrap();
+        assert!(groups.group(&header).is_err());
+        assert!(HeaderGroups::new(HeaderColumn::Abundance, None).is_err());
+        assert!(HeaderGroups::new(HeaderColumn::Counter, None).is_err());
+        assert!(HeaderGroups::new(HeaderColumn::Accession, Some(1.0)).is_err());
+        assert!(HeaderGroups::new(HeaderColumn::Links, Some(0.0)).is_err());
+    }
+}
@@ -0,0 +1,455 @@
+//! Input files: expansion of directories, glob patterns and file-of-filenames, and
+//! reading of FASTA and FASTQ records with automatic decompression
+
+use bio::io::{fasta, fastq};
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use xz2::read::XzDecoder;
+
+/// Input path standing for the standard input
+pub const STDIN_PATH: &str = "-";
+
+/// A decompressed input, ready to be parsed
+type Input = Box<dyn BufRead + Send>;
+
+/// Number of bytes needed to recognize every compression magic
+const MAGIC_LEN: u64 = 6;
+
+/// Expands the command-line inputs and the optional file-of-filenames into a list of files
+///
+/// Directories are replaced by the (non-hidden) files they contain, and inputs that do not
+/// exist but contain `*`, `?` or `[` are expanded as glob patterns, both in sorted order.
+/// [`STDIN_PATH`] is kept as is, and may only be given once.
+pub fn expand_inputs(inputs: &[String], fof: Option<&Path>) -> io::Result<Vec<PathBuf>> {
+    let mut names = inputs.to_vec();
+    if let Some(fof) = fof {
+        let reader = BufReader::new(File::open(fof).map_err(|e| with_path(e, fof))?);
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                names.push(line.to_string());
+            }
+        }
+    }
+
+    if names.iter().filter(|name| *name == STDIN_PATH).count() > 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the standard input (`-`) can only be read once",
+        ));
+    }
+
+    let mut files = Vec::new();
+    for name in &names {
+        let path = Path::new(name);
+        if name == STDIN_PATH {
+            files.push(path.to_path_buf());
+        } else if path.is_dir() {
+            let mut entries = Vec::new();
+            for entry in fs::read_dir(path).map_err(|e| with_path(e, path))? {
+                let entry = entry?;
+                let hidden = entry.file_name().to_string_lossy().starts_with('.');
+                if !hidden && entry.file_type()?.is_file() {
+                    entries.push(entry.path());
+                }
+            }
+            entries.sort();
+            files.extend(entries);
+        } else if !path.exists() && name.contains(['*', '?', '[']) {
+            let options = glob::MatchOptions {
+                require_literal_leading_dot: true,
+                ..Default::default()
+            };
+            let pattern = glob::glob_with(name, options)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            let mut matches = Vec::new();
+            for entry in pattern {
+                let entry = entry.map_err(io::Error::from)?;
+                if entry.is_file() {
+                    matches.push(entry);
+                }
+            }
+            if matches.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{}: no file matches", name),
+                ));
+            }
+            matches.sort();
+            files.extend(matches);
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+    Ok(files)
+}
+
+/// Name of an input without its directory and its sequence or compression extensions
+///
+/// The standard input is named `stdin`.
+pub fn input_stem(path: &Path) -> String {
+    const EXTENSIONS: [&str; 14] = [
+        "zst", "gz", "bgz", "bz2", "xz", "fa", "fasta", "fna", "fq", "fastq", "contigs", "unitigs",
+        "reads", "txt",
+    ];
+
+    if path == Path::new(STDIN_PATH) {
+        return "stdin".to_string();
+    }
+    let mut name = path
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+    while let Some((stem, extension)) = name.rsplit_once('.') {
+        if stem.is_empty() || !EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()) {
+            break;
+        }
+        name.truncate(stem.len());
+    }
+    name
+}
+
+/// Sequence file format, detected from the first character of the (decompressed) input
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Fasta,
+    Fastq,
+}
+
+/// A sequence record read from a FASTA or FASTQ file
+#[derive(Clone, Debug)]
+pub enum Record {
+    Fasta(fasta::Record),
+    Fastq(fastq::Record),
+}
+
+impl Record {
+    /// Identifier of the record
+    pub fn id(&self) -> &str {
+        match self {
+            Record::Fasta(record) => record.id(),
+            Record::Fastq(record) => record.id(),
+        }
+    }
+
+    /// Description of the record, following the identifier in the header
+    pub fn desc(&self) -> Option<&str> {
+        match self {
+            Record::Fasta(record) => record.desc(),
+            Record::Fastq(record) => record.desc(),
+        }
+    }
+
+    /// Nucleotide sequence of the record
+    pub fn seq(&self) -> &[u8] {
+        match self {
+            Record::Fasta(record) => record.seq(),
+            Record::Fastq(record) => record.seq(),
+        }
+    }
+
+    /// The record without its first `start` and last `end` bases (empty if it is not longer)
+    pub fn trimmed(&self, start: usize, end: usize) -> Record {
+        let len = self.seq().len();
+        let range = if start + end < len {
+            start..len - end
+        } else {
+            0..0
+        };
+        match self {
+            Record::Fasta(record) => Record::Fasta(fasta::Record::with_attrs(
+                record.id(),
+                record.desc(),
+                &record.seq()[range],
+            )),
+            Record::Fastq(record) => Record::Fastq(fastq::Record::with_attrs(
+                record.id(),
+                record.desc(),
+                &record.seq()[range.clone()],
+                &record.qual()[range],
+            )),
+        }
+    }
+}
+
+/// Wraps `reader` into a decompressor chosen from its magic bytes
+/// (zstd, gzip and bgzf, bzip2, xz), or returns it as is
+fn decompress(mut reader: Input) -> io::Result<Input> {
+    // A single read, e.g. from a pipe, may return fewer bytes than the magics need
+    let mut magic = Vec::new();
+    (&mut reader).take(MAGIC_LEN).read_to_end(&mut magic)?;
+    let mut reader: Input = Box::new(io::Cursor::new(magic).chain(reader));
+    let magic = reader.fill_buf()?;
+    Ok(if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Box::new(BufReader::new(zstd::Decoder::with_buffer(reader)?))
+    } else if magic.starts_with(&[0x1f, 0x8b]) {
+        // bgzf files are series of gzip members
+        Box::new(BufReader::new(MultiGzDecoder::new(reader)))
+    } else if magic.starts_with(b"BZh") {
+        Box::new(BufReader::new(MultiBzDecoder::new(reader)))
+    } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        Box::new(BufReader::new(XzDecoder::new_multi_decoder(reader)))
+    } else {
+        reader
+    })
+}
+
+/// Detects whether a decompressed input is FASTA or FASTQ from its first character
+fn detect_format(reader: &mut Input) -> io::Result<Format> {
+    match reader.fill_buf()?.first() {
+        None | Some(b'>') => Ok(Format::Fasta),
+        Some(b'@') => Ok(Format::Fastq),
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a FASTA or FASTQ file",
+        )),
+    }
+}
+
+/// A decompressed FASTA or FASTQ input, ready to be parsed
+///
+/// The standard input can only be opened once: when it was opened to check its format, it is
+/// passed to [`read_inputs`] to read its records.
+pub struct OpenedInput {
+    format: Format,
+    reader: Input,
+}
+
+impl OpenedInput {
+    /// Opens and decompresses a file, or the standard input if the path is [`STDIN_PATH`], and
+    /// detects its format
+    pub fn open(file_path: &Path) -> io::Result<Self> {
+        let mut reader = open_decompressed(file_path)?;
+        Ok(OpenedInput {
+            format: detect_format(&mut reader)?,
+            reader,
+        })
+    }
+
+    /// Format of the input
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Iterates over the records of the input
+    pub fn records(self) -> Box<dyn Iterator<Item = io::Result<Record>>> {
+        parse_records(self.format, self.reader)
+    }
+}
+
+/// Opens and decompresses a file, or the standard input
+fn open_decompressed(file_path: &Path) -> io::Result<Input> {
+    let raw: Input = if file_path == Path::new(STDIN_PATH) {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        Box::new(BufReader::new(File::open(file_path)?))
+    };
+    decompress(raw)
+}
+
+/// Opens a FASTA or FASTQ file, compressed or not, or the standard input if the path
+/// is [`STDIN_PATH`], and iterates over its records
+pub fn open_sequence_file(
+    file_path: &Path,
+) -> io::Result<Box<dyn Iterator<Item = io::Result<Record>>>> {
+    Ok(OpenedInput::open(file_path)?.records())
+}
+
+/// Opens a FASTA or FASTQ file, or a list of sequences (e.g. k-mers) with one per line, compressed
+/// or not, and iterates over its records
+///
+/// Empty lines and lines starting with `#` of a list are ignored; the other ones become records
+/// named after their line number.
+pub fn open_sequences_or_list(
+    file_path: &Path,
+) -> io::Result<Box<dyn Iterator<Item = io::Result<Record>>>> {
+    let mut reader = open_decompressed(file_path)?;
+    if let Ok(format) = detect_format(&mut reader) {
+        return Ok(parse_records(format, reader));
+    }
+    Ok(Box::new(reader.lines().enumerate().filter_map(
+        |(i, line)| match line {
+            Ok(line) => {
+                let line = line.trim();
+                let sequence = !line.is_empty() && !line.starts_with('#');
+                let id = (i + 1).to_string();
+                sequence.then(|| {
+                    Ok(Record::Fasta(fasta::Record::with_attrs(
+                        &id,
+                        None,
+                        line.as_bytes(),
+                    )))
+                })
+            }
+            Err(e) => Some(Err(e)),
+        },
+    )))
+}
+
+/// Iterates over the records of a decompressed FASTA or FASTQ input
+fn parse_records(format: Format, reader: Input) -> Box<dyn Iterator<Item = io::Result<Record>>> {
+    match format {
+        Format::Fasta => Box::new(
+            fasta::Reader::from_bufread(reader)
+                .records()
+                .map(|record| record.map(Record::Fasta)),
+        ),
+        Format::Fastq => Box::new(fastq::Reader::from_bufread(reader).records().map(|record| {
+            record
+                .map(Record::Fastq)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        })),
+    }
+}
+
+/// Records of all input files, one file after the other
+pub fn read_records(paths: &[PathBuf]) -> impl Iterator<Item = io::Result<Record>> + '_ {
+    read_inputs(paths, None)
+}
+
+/// Records of all input files, one file after the other, the standard input being read from
+/// `stdin` if it was already opened
+pub fn read_inputs(
+    paths: &[PathBuf],
+    mut stdin: Option<OpenedInput>,
+) -> impl Iterator<Item = io::Result<Record>> + '_ {
+    paths.iter().flat_map(
+        move |path| -> Box<dyn Iterator<Item = io::Result<Record>>> {
+            let opened = match stdin.take_if(|_| path == Path::new(STDIN_PATH)) {
+                Some(stdin) => Ok(stdin),
+                None => OpenedInput::open(path),
+            };
+            match opened {
+                Ok(input) => Box::new(
+                    input
+                        .records()
+                        .map(move |record| record.map_err(|e| with_path(e, path))),
+                ),
+                Err(e) => Box::new(std::iter::once(Err(with_path(e, path)))),
+            }
+        },
+    )
+}
+
+/// Prefixes an I/O error with the path it relates to
+pub(crate) fn with_path(error: io::Error, path: &Path) -> io::Error {
+    io::Error::new(error.kind(), format!("{}: {}", path.display(), error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// A reader returning one byte per read, as a pipe may
+    struct Trickle(io::Cursor<Vec<u8>>);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    const FASTA: &[u8] = b">SRR1_1 ka:f:2.5\nACGTACGT\n>SRR1_2 ka:f:1\nGGCCA\n";
+    const FASTQ: &[u8] = b"@read1\nACGTACGT\n+\nIIIIIIII\n@read2\nGGCCA\n+\nIIIII\n";
+
+    /// A BGZF block: a gzip member whose `BC` extra field holds its size minus 1
+    fn bgzf_block(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::GzBuilder::new()
+            .extra(vec![b'B', b'C', 2, 0, 0, 0])
+            .write(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        let mut block = encoder.finish().unwrap();
+        let size = (block.len() - 1) as u16;
+        block[16..18].copy_from_slice(&size.to_le_bytes());
+        block
+    }
+
+    /// `data` as is and compressed with each supported codec
+    fn compressed(data: &[u8]) -> Vec<(&'static str, Vec<u8>)> {
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(data).unwrap();
+        // Two blocks and the empty end-of-file block
+        let (first, second) = data.split_at(data.len() / 2);
+        let bgzf = [bgzf_block(first), bgzf_block(second), bgzf_block(b"")].concat();
+        let mut bzip2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        bzip2.write_all(data).unwrap();
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(data).unwrap();
+        vec![
+            ("plain", data.to_vec()),
+            ("zstd", zstd::encode_all(data, 0).unwrap()),
+            ("gzip", gzip.finish().unwrap()),
+            ("bgzf", bgzf),
+            ("bzip2", bzip2.finish().unwrap()),
+            ("xz", xz.finish().unwrap()),
+        ]
+    }
+
+    /// Checks the format and the records of `data` with each codec
+    fn check(data: &[u8], format: Format) {
+        let dir = tempfile::tempdir().unwrap();
+        for (codec, bytes) in compressed(data) {
+            let path = dir.path().join(codec);
+            fs::write(&path, bytes).unwrap();
+            assert_eq!(
+                OpenedInput::open(&path).unwrap().format(),
+                format,
+                "{}",
+                codec
+            );
+            let records: Vec<Record> = read_records(&[path]).map(Result::unwrap).collect();
+            let ids: Vec<&str> = records.iter().map(Record::id).collect();
+            let seqs: Vec<&[u8]> = records.iter().map(Record::seq).collect();
+            assert_eq!(seqs, [&b"ACGTACGT"[..], b"GGCCA"], "{}", codec);
+            match format {
+                Format::Fasta => {
+                    assert_eq!(ids, ["SRR1_1", "SRR1_2"]);
+                    assert_eq!(records[0].desc(), Some("ka:f:2.5"));
+                    assert!(matches!(records[0], Record::Fasta(_)));
+                }
+                Format::Fastq => {
+                    assert_eq!(ids, ["read1", "read2"]);
+                    assert!(matches!(records[0], Record::Fastq(_)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reads_fasta_with_each_compression() {
+        check(FASTA, Format::Fasta);
+    }
+
+    #[test]
+    fn reads_fastq_with_each_compression() {
+        check(FASTQ, Format::Fastq);
+    }
+
+    #[test]
+    fn rejects_other_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("table.tsv");
+        fs::write(&path, "id\tseq\n").unwrap();
+        assert!(OpenedInput::open(&path).is_err());
+    }
+
+    #[test]
+    fn detects_compression_from_short_reads() {
+        for (codec, bytes) in compressed(FASTA) {
+            let raw: Input = Box::new(BufReader::new(Trickle(io::Cursor::new(bytes))));
+            let mut reader = decompress(raw).unwrap();
+            let format = detect_format(&mut reader).unwrap();
+            let records: Vec<Record> = parse_records(format, reader)
+                .collect::<io::Result<_>>()
+                .unwrap();
+            assert_eq!(records.len(), 2, "{}", codec);
+            assert_eq!(records[0].desc(), Some("ka:f:2.5"), "{}", codec);
+            assert_eq!(records[1].seq(), b"GGCCA", "{}", codec);
+        }
+    }
+}
@@ -0,0 +1,405 @@
+//! Bit-encoded k-mer representations, from a single 64-bit word up to several words
+
+use std::fmt::Debug;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// A k-mer packed with 2 bits per nucleotide, the first nucleotide in the most significant bits
+///
+/// The ordering of encoded k-mers is the lexicographic ordering of the nucleotide sequences,
+/// so the canonical k-mer is the minimum of a k-mer and its reverse complement.
+pub trait Kmer: Copy + Eq + Ord + Hash + Debug + Send + Sync + 'static {
+    /// Largest k-mer size this representation can hold
+    const MAX_K: usize;
+
+    /// Size of the little-endian serialization of the k-mer
+    const BYTES: usize;
+
+    /// The k-mer made only of `A`s
+    fn zero() -> Self;
+
+    /// Appends a nucleotide (2-bit value) on the right, keeping only the last `k` nucleotides
+    fn push(self, bits: u64, k: usize) -> Self;
+
+    /// Prepends a nucleotide (2-bit value) on the left, dropping the last of the `k` nucleotides
+    fn push_front(self, bits: u64, k: usize) -> Self;
+
+    /// Writes the k-mer into the first [`Kmer::BYTES`] bytes of `out`
+    fn write_bytes(&self, out: &mut [u8]);
+
+    /// Reads a k-mer written by [`Kmer::write_bytes`]
+    fn read_bytes(bytes: &[u8]) -> Self;
+}
+
+/// Hash of a k-mer that is stable across runs, used to split k-mers into shards or buckets
+pub fn kmer_hash<K: Kmer>(kmer: &K) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    kmer.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Convert a nucleotide to its 2-bit representation
+///
+/// The complement of a nucleotide `n` is encoded as `3 - n`.
+pub const fn nucleotide_to_bits(n: u8) -> Option<u64> {
+    match n {
+        b'A' | b'a' => Some(0b00),
+        b'C' | b'c' => Some(0b01),
+        b'G' | b'g' => Some(0b10),
+        b'T' | b't' => Some(0b11),
+        _ => None,
+    }
+}
+
+impl Kmer for u64 {
+    const MAX_K: usize = 32;
+    const BYTES: usize = 8;
+
+    fn zero() -> Self {
+        0
+    }
+
+    fn push(self, bits: u64, k: usize) -> Self {
+        let mask = if k == 32 {
+            u64::MAX
+        } else {
+            (1 << (2 * k)) - 1
+        };
+        ((self << 2) | bits) & mask
+    }
+
+    fn push_front(self, bits: u64, k: usize) -> Self {
+        (self >> 2) | (bits << (2 * (k - 1)))
+    }
+
+    fn write_bytes(&self, out: &mut [u8]) {
+        out[..8].copy_from_slice(&self.to_le_bytes());
+    }
+
+    fn read_bytes(bytes: &[u8]) -> Self {
+        u64::from_le_bytes(bytes[..8].try_into().unwrap())
+    }
+}
+
+impl Kmer for u128 {
+    const MAX_K: usize = 64;
+    const BYTES: usize = 16;
+
+    fn zero() -> Self {
+        0
+    }
+
+    fn push(self, bits: u64, k: usize) -> Self {
+        let mask = if k == 64 {
+            u128::MAX
+        } else {
+            (1 << (2 * k)) - 1
+        };
+        ((self << 2) | bits as u128) & mask
+    }
+
+    fn push_front(self, bits: u64, k: usize) -> Self {
+        (self >> 2) | ((bits as u128) << (2 * (k - 1)))
+    }
+
+    fn write_bytes(&self, out: &mut [u8]) {
+        out[..16].copy_from_slice(&self.to_le_bytes());
+    }
+
+    fn read_bytes(bytes: &[u8]) -> Self {
+        u128::from_le_bytes(bytes[..16].try_into().unwrap())
+    }
+}
+
+/// A k-mer spread over `N` 64-bit words, most significant word first
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MultiWord<const N: usize>([u64; N]);
+
+impl<const N: usize> Kmer for MultiWord<N> {
+    const MAX_K: usize = 32 * N;
+    const BYTES: usize = 8 * N;
+
+    fn zero() -> Self {
+        MultiWord([0; N])
+    }
+
+    fn push(self, bits: u64, k: usize) -> Self {
+        let mut words = self.0;
+        for i in 0..N - 1 {
+            words[i] = (words[i] << 2) | (words[i + 1] >> 62);
+        }
+        words[N - 1] = (words[N - 1] << 2) | bits;
+
+        // Clear the bits above the 2k least significant ones
+        let unused = 64 * N - 2 * k;
+        for word in words.iter_mut().take(unused / 64) {
+            *word = 0;
+        }
+        if let Some(word) = words.get_mut(unused / 64) {
+            *word &= u64::MAX >> (unused % 64);
+        }
+        MultiWord(words)
+    }
+
+    fn push_front(self, bits: u64, k: usize) -> Self {
+        let mut words = self.0;
+        for i in (1..N).rev() {
+            words[i] = (words[i] >> 2) | (words[i - 1] << 62);
+        }
+        words[0] >>= 2;
+
+        let offset = 2 * (k - 1);
+        words[N - 1 - offset / 64] |= bits << (offset % 64);
+        MultiWord(words)
+    }
+
+    fn write_bytes(&self, out: &mut [u8]) {
+        // Least significant word first, to match the integer representations
+        for (chunk, word) in out[..8 * N].chunks_exact_mut(8).zip(self.0.iter().rev()) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+    }
+
+    fn read_bytes(bytes: &[u8]) -> Self {
+        let mut words = [0; N];
+        for (word, chunk) in words.iter_mut().rev().zip(bytes[..8 * N].chunks_exact(8)) {
+            *word = u64::from_le_bytes(chunk.try_into().unwrap());
+        }
+        MultiWord(words)
+    }
+}
+
+/// A sequence without its first and last `trim` bases (e.g. the overlaps shared with
+/// neighbouring unitigs), empty if it is not longer than twice `trim`
+pub fn trim_sequence(seq: &[u8], trim: usize) -> &[u8] {
+    if seq.len() > 2 * trim {
+        &seq[trim..seq.len() - trim]
+    } else {
+        &[]
+    }
+}
+
+/// Number of k-mers of a sequence trimmed by `trim` bases at both ends that contain only ACGT characters
+pub fn count_valid_kmers(seq: &[u8], k: usize, trim: usize) -> u64 {
+    trim_sequence(seq, trim)
+        .split(|&n| nucleotide_to_bits(n).is_none())
+        .map(|run| (run.len() + 1).saturating_sub(k) as u64)
+        .sum()
+}
+
+/// Iterates over the bit-encoded k-mers of a sequence trimmed by `trim` bases at both ends,
+/// considering canonical representation if required
+pub fn generate_encoded_kmers<K: Kmer>(
+    seq: &[u8],
+    k: usize,
+    canonical: bool,
+    trim: usize,
+) -> KmerIter<'_, K> {
+    KmerIter::new(trim_sequence(seq, trim), k, canonical)
+}
+
+/// Iterator over the k-mers of a sequence, updating the encoding in O(1) per nucleotide
+///
+/// Windows containing a non-ACGT character are skipped: the encoding restarts after it.
+pub struct KmerIter<'a, K: Kmer> {
+    seq: &'a [u8],
+    k: usize,
+    canonical: bool,
+    /// Position of the next nucleotide to read
+    pos: usize,
+    /// Number of consecutive valid nucleotides ending at `pos`
+    valid: usize,
+    forward: K,
+    reverse: K,
+}
+
+impl<'a, K: Kmer> KmerIter<'a, K> {
+    /// Iterates over the k-mers of `seq`, as their canonical form if `canonical` is set
+    pub fn new(seq: &'a [u8], k: usize, canonical: bool) -> Self {
+        KmerIter {
+            seq,
+            k,
+            canonical,
+            pos: 0,
+            valid: 0,
+            forward: K::zero(),
+            reverse: K::zero(),
+        }
+    }
+}
+
+impl<K: Kmer> Iterator for KmerIter<'_, K> {
+    type Item = K;
+
+    fn next(&mut self) -> Option<K> {
+        while let Some(&n) = self.seq.get(self.pos) {
+            self.pos += 1;
+            let Some(bits) = nucleotide_to_bits(n) else {
+                self.valid = 0;
+                continue;
+            };
+
+            self.forward = self.forward.push(bits, self.k);
+            if self.canonical {
+                self.reverse = self.reverse.push_front(3 - bits, self.k);
+            }
+            self.valid += 1;
+            if self.valid >= self.k {
+                return Some(if self.canonical {
+                    self.forward.min(self.reverse)
+                } else {
+                    self.forward
+                });
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 64-bit words of an encoded k-mer, least significant first
+    trait Words {
+        fn words(&self) -> Vec<u64>;
+    }
+
+    impl Words for u64 {
+        fn words(&self) -> Vec<u64> {
+            vec![*self]
+        }
+    }
+
+    impl Words for u128 {
+        fn words(&self) -> Vec<u64> {
+            vec![*self as u64, (*self >> 64) as u64]
+        }
+    }
+
+    impl<const N: usize> Words for MultiWord<N> {
+        fn words(&self) -> Vec<u64> {
+            self.0.iter().rev().copied().collect()
+        }
+    }
+
+    /// A pseudo-random sequence with lowercase bases and a few `N` runs
+    fn sequence(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        (0..len)
+            .map(|i| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                match i {
+                    300 | 301 | 650 => b'N',
+                    _ => b"ACGTacgt"[(state >> 61) as usize],
+                }
+            })
+            .collect()
+    }
+
+    /// Encodes a k-mer position by position into `len` words, least significant first
+    fn naive_encode(kmer: &[u8], len: usize) -> Vec<u64> {
+        let mut words = vec![0; len];
+        for (i, &n) in kmer.iter().enumerate() {
+            let bit = 2 * (kmer.len() - 1 - i);
+            words[bit / 64] |= nucleotide_to_bits(n).unwrap() << (bit % 64);
+        }
+        words
+    }
+
+    fn reverse_complement(kmer: &[u8]) -> Vec<u8> {
+        kmer.iter()
+            .rev()
+            .map(|n| match n.to_ascii_uppercase() {
+                b'A' => b'T',
+                b'C' => b'G',
+                b'G' => b'C',
+                _ => b'A',
+            })
+            .collect()
+    }
+
+    /// Checks the k-mers of the iterator against the naive encodings of each valid window
+    fn check<K: Kmer + Words>(k: usize) {
+        let seq = sequence(1000);
+        let len = K::zero().words().len();
+        for canonical in [false, true] {
+            let expected: Vec<Vec<u64>> = seq
+                .windows(k)
+                .filter(|window| window.iter().all(|&n| nucleotide_to_bits(n).is_some()))
+                .map(|window| {
+                    let forward = window.to_ascii_uppercase();
+                    let reverse = reverse_complement(window);
+                    if canonical && reverse < forward {
+                        naive_encode(&reverse, len)
+                    } else {
+                        naive_encode(&forward, len)
+                    }
+                })
+                .collect();
+            let kmers: Vec<Vec<u64>> = KmerIter::<K>::new(&seq, k, canonical)
+                .map(|kmer| kmer.words())
+                .collect();
+            assert!(!expected.is_empty());
+            assert_eq!(kmers, expected, "k={} canonical={}", k, canonical);
+            assert_eq!(count_valid_kmers(&seq, k, 0), expected.len() as u64);
+        }
+    }
+
+    #[test]
+    fn single_word_kmers() {
+        for k in [1, 2, 21, 31, 32] {
+            check::<u64>(k);
+        }
+        for k in [1, 32, 33, 63, 64] {
+            check::<u128>(k);
+        }
+    }
+
+    #[test]
+    fn multi_word_kmers_across_word_boundaries() {
+        for k in [1, 31, 32, 33, 63, 64] {
+            check::<MultiWord<2>>(k);
+        }
+        for k in [32, 33, 64, 65, 95, 96, 97, 127, 128] {
+            check::<MultiWord<4>>(k);
+        }
+        for k in [129, 192, 193, 255, 256] {
+            check::<MultiWord<8>>(k);
+        }
+    }
+
+    #[test]
+    fn trims_both_ends_before_extracting_kmers() {
+        assert_eq!(trim_sequence(b"AACGTTT", 2), b"CGT");
+        assert!(trim_sequence(b"ACGT", 2).is_empty());
+        assert_eq!(count_valid_kmers(b"AACGTTT", 3, 2), 1);
+        assert_eq!(count_valid_kmers(b"NACGTAN", 3, 1), 3);
+        assert_eq!(count_valid_kmers(b"AACGTTT", 4, 2), 0);
+        let kmers: Vec<Vec<u64>> = generate_encoded_kmers::<u64>(b"TTACGTAA", 3, false, 2)
+            .map(|kmer| kmer.words())
+            .collect();
+        assert_eq!(kmers, [b"ACG", b"CGT"].map(|kmer| naive_encode(kmer, 1)));
+    }
+
+    #[test]
+    fn restarts_after_invalid_bases() {
+        let kmers: Vec<Vec<u64>> = KmerIter::<u64>::new(b"ACGNTTGCA", 3, false)
+            .map(|kmer| kmer.words())
+            .collect();
+        let expected = [b"ACG", b"TTG", b"TGC", b"GCA"].map(|kmer| naive_encode(kmer, 1));
+        assert_eq!(kmers, expected);
+        assert_eq!(KmerIter::<u64>::new(b"ACNGT", 3, true).count(), 0);
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let seq = sequence(300);
+        let mut bytes = [0; MultiWord::<4>::BYTES];
+        for kmer in KmerIter::<MultiWord<4>>::new(&seq, 100, true) {
+            kmer.write_bytes(&mut bytes);
+            assert_eq!(MultiWord::<4>::read_bytes(&bytes), kmer);
+        }
+    }
+}
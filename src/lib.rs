@@ -0,0 +1,39 @@
+//! k-mer spectra of Logan unitigs and contigs, where each k-mer is weighted by the
+//! abundance found in the headers of the sequences it belongs to.
+//!
+//! [`SpectrumBuilder`] configures and computes a [`Spectrum`] from FASTA/FASTQ files or records,
+//! [`find_peaks`] locates its error valley and coverage peaks, and [`fit_model`] estimates the
+//! genome size, heterozygosity and coverage from it. Saved spectra are merged and compared
+//! with the [`load`] functions and [`compare_spectra`].
+
+pub mod abundance;
+pub mod compare;
+pub mod count;
+pub mod disk;
+pub mod dump;
+pub mod graph;
+pub mod header;
+pub mod input;
+pub mod kmer;
+pub mod load;
+pub mod model;
+pub mod output;
+pub mod peaks;
+pub mod profile;
+pub mod spectrum;
+pub mod stats;
+
+pub use abundance::{
+    AbundanceSource, Aggregation, HeaderAbundance, HeaderField, HeaderFormat, MissingAbundance,
+    Rounding,
+};
+pub use compare::{compare_spectra, Comparison};
+pub use dump::{DumpFormat, KmerDump};
+pub use graph::{GraphStats, LinkGraph};
+pub use header::{HeaderColumn, HeaderFilter, HeaderGroup, HeaderGroups, LoganHeader};
+pub use input::Record;
+pub use model::{fit_model, ModelFit};
+pub use peaks::{find_peaks, Peaks, Smoothing};
+pub use profile::{Profiles, RecordProfile};
+pub use spectrum::{FastPath, Spectrum, SpectrumBuilder, MAX_K};
+pub use stats::{RunStats, SkipReason};
@@ -0,0 +1,237 @@
+//! Loading of saved spectra: histograms written in any output format, and binary k-mer dumps
+
+use serde_json::Value;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::dump::{merge_dumps, MAGIC};
+use crate::input::with_path;
+use crate::spectrum::Spectrum;
+
+/// Whether a file is a binary k-mer dump rather than a histogram
+pub fn is_dump(path: &Path) -> io::Result<bool> {
+    let mut magic = [0; 8];
+    let mut file = File::open(path).map_err(|e| with_path(e, path))?;
+    Ok(file.read_exact(&mut magic).is_ok() && &magic == MAGIC)
+}
+
+/// Reads a histogram written in the TSV, CSV, `histo` or JSON format
+///
+/// Without a bin width in the JSON metadata, the frequencies are the lower bounds of bins of
+/// width `bin_width`, or integers if it is `None`.
+pub fn read_histogram(path: &Path, bin_width: Option<f64>) -> io::Result<Spectrum> {
+    let text = fs::read_to_string(path).map_err(|e| with_path(e, path))?;
+    let invalid = |message: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), message),
+        )
+    };
+
+    let mut rows = Vec::new();
+    let mut bin_width = bin_width;
+    if text.trim_start().starts_with('{') {
+        let object: Value = serde_json::from_str(&text).map_err(|e| invalid(e.to_string()))?;
+        if let Some(width) = object.get("bin_width").and_then(Value::as_f64) {
+            if bin_width.is_some_and(|bin_width| bin_width != width) {
+                return Err(invalid(format!("histogram of bin width {}", width)));
+            }
+            bin_width = Some(width);
+        }
+        let histogram = object
+            .get("histogram")
+            .and_then(Value::as_array)
+            .ok_or_else(|| invalid("no histogram".to_string()))?;
+        for row in histogram {
+            let frequency = row.get("frequency").and_then(Value::as_f64);
+            let count = row.get("count").and_then(Value::as_u64);
+            match frequency.zip(count) {
+                Some(row) => rows.push(row),
+                None => return Err(invalid(format!("invalid histogram row {}", row))),
+            }
+        }
+    } else {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line
+                .split(['\t', ',', ' '])
+                .filter(|field| !field.is_empty());
+            let frequency = fields.next().and_then(|field| field.parse::<f64>().ok());
+            let count = fields.next().and_then(|field| field.parse::<u64>().ok());
+            match frequency.zip(count) {
+                Some(row) => rows.push(row),
+                // Header line of the TSV and CSV formats
+                None if rows.is_empty() && frequency.is_none() => {}
+                None => return Err(invalid(format!("invalid histogram line {}", i + 1))),
+            }
+        }
+    }
+
+    let mut spectrum = Spectrum::new(bin_width);
+    for (frequency, count) in rows {
+        let bin = frequency / bin_width.unwrap_or(1.0);
+        if bin < 0.0 || (bin - bin.round()).abs() > 1e-6 {
+            return Err(invalid(format!(
+                "frequency {} is not the lower bound of a bin, set the bin width of the histogram",
+                frequency
+            )));
+        }
+        spectrum.add_bin(bin.round() as u64, count);
+    }
+    Ok(spectrum)
+}
+
+/// Loads the spectrum of a saved histogram or of a binary k-mer dump
+///
+/// k-mer counts of a dump are binned by `bin_width`, which is otherwise the bin width of the
+/// histogram when it does not say it.
+pub fn load_spectrum(path: &Path, bin_width: Option<f64>) -> io::Result<Spectrum> {
+    if is_dump(path)? {
+        merge_dumps(&[path.to_path_buf()], bin_width)
+    } else {
+        read_histogram(path, bin_width)
+    }
+}
+
+/// Merges saved spectra: the k-mer counts of binary dumps are summed before their spectrum is
+/// computed, whereas histograms are summed bin by bin
+///
+/// Dumps and histograms cannot be mixed, and all histograms must have the same bin width.
+pub fn merge_spectra(paths: &[PathBuf], bin_width: Option<f64>) -> io::Result<Spectrum> {
+    let dumps = paths
+        .iter()
+        .map(|path| is_dump(path))
+        .collect::<io::Result<Vec<_>>>()?;
+    if dumps.iter().all(|&dump| dump) {
+        return merge_dumps(paths, bin_width);
+    }
+    if let Some(i) = dumps.iter().position(|&dump| dump) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{}: k-mer dumps and histograms cannot be merged together",
+                paths[i].display()
+            ),
+        ));
+    }
+
+    let mut merged: Option<Spectrum> = None;
+    for path in paths {
+        let spectrum = read_histogram(path, bin_width)?;
+        match &mut merged {
+            Some(merged) if merged.bin_width() != spectrum.bin_width() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{}: histogram of another bin width", path.display()),
+                ))
+            }
+            Some(merged) => merged.merge(&spectrum),
+            None => merged = Some(spectrum),
+        }
+    }
+    Ok(merged.unwrap_or_else(|| Spectrum::new(bin_width)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{write_histogram, OutputFormat};
+    use serde_json::{json, Map};
+
+    fn spectrum(bins: &[(u64, u64)], bin_width: Option<f64>) -> Spectrum {
+        let mut spectrum = Spectrum::new(bin_width);
+        for &(bin, kmers) in bins {
+            spectrum.add_bin(bin, kmers);
+        }
+        spectrum
+    }
+
+    /// Writes a spectrum in `format`, with its bin width in the JSON metadata as the subcommands do
+    fn save(path: &Path, spectrum: &Spectrum, format: OutputFormat) {
+        let mut metadata = Map::new();
+        metadata.insert("bin_width".to_string(), json!(spectrum.bin_width()));
+        let mut output = File::create(path).unwrap();
+        write_histogram(&mut output, spectrum, format, None, metadata).unwrap();
+    }
+
+    #[test]
+    fn histograms_round_trip_through_every_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("histogram");
+        let integer = spectrum(&[(1, 5), (3, 1), (4, 2)], None);
+        for format in [
+            OutputFormat::Tsv,
+            OutputFormat::Csv,
+            OutputFormat::Json,
+            OutputFormat::Histo,
+        ] {
+            save(&path, &integer, format);
+            assert_eq!(
+                read_histogram(&path, None).unwrap(),
+                integer,
+                "{:?}",
+                format
+            );
+        }
+
+        let binned = spectrum(&[(0, 2), (1, 5), (3, 1)], Some(0.5));
+        for format in [OutputFormat::Tsv, OutputFormat::Csv, OutputFormat::Json] {
+            save(&path, &binned, format);
+            let read = read_histogram(&path, Some(0.5)).unwrap();
+            assert_eq!(read, binned, "{:?}", format);
+        }
+        // JSON histograms carry their bin width
+        assert_eq!(read_histogram(&path, None).unwrap(), binned);
+    }
+
+    #[test]
+    fn rejects_mismatched_bin_widths() {
+        let dir = tempfile::tempdir().unwrap();
+        let json = dir.path().join("binned.json");
+        save(&json, &spectrum(&[(1, 5)], Some(0.5)), OutputFormat::Json);
+        assert!(read_histogram(&json, Some(1.0)).is_err());
+
+        let tsv = dir.path().join("integer.tsv");
+        save(&tsv, &spectrum(&[(1, 5)], None), OutputFormat::Tsv);
+        let error = merge_spectra(&[json, tsv.clone()], None).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+        // 0.75 is not the lower bound of a bin of width 0.5
+        fs::write(&tsv, "K-mer Frequency\tCount\n0.75\t3\n").unwrap();
+        let error = read_histogram(&tsv, Some(0.5)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn merges_histograms_bin_by_bin() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = [
+            dir.path().join("first.tsv"),
+            dir.path().join("second.histo"),
+        ];
+        save(
+            &paths[0],
+            &spectrum(&[(1, 5), (3, 1)], None),
+            OutputFormat::Tsv,
+        );
+        save(
+            &paths[1],
+            &spectrum(&[(1, 2), (4, 2)], None),
+            OutputFormat::Histo,
+        );
+        assert_eq!(
+            merge_spectra(&paths, None).unwrap(),
+            spectrum(&[(1, 7), (3, 1), (4, 2)], None)
+        );
+
+        let dump = dir.path().join("dump");
+        fs::write(&dump, MAGIC).unwrap();
+        assert!(is_dump(&dump).unwrap());
+        let error = merge_spectra(&[paths[0].clone(), dump], None).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -1,176 +1,915 @@
-use bio::io::fasta;
-use clap::Parser;
-use regex::Regex;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufReader, Read};
-use std::path::Path;
-use zstd::Decoder;
+use clap::error::ErrorKind;
+use clap::{ArgGroup, CommandFactory, Parser, Subcommand, ValueEnum};
+use serde_json::{json, Map, Value};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use logan_kmer_spectrum::graph::write_gfa;
+use logan_kmer_spectrum::input::{expand_inputs, input_stem, open_sequences_or_list, read_records};
+use logan_kmer_spectrum::kmer::nucleotide_to_bits;
+use logan_kmer_spectrum::load::{load_spectrum, merge_spectra};
+use logan_kmer_spectrum::output::{
+    write_comparison, write_histogram, write_model, write_peaks, write_profiles, write_stats,
+    OutputFormat,
+};
+use logan_kmer_spectrum::{
+    compare_spectra, find_peaks, fit_model, AbundanceSource, Aggregation, DumpFormat, FastPath,
+    HeaderColumn, HeaderField, HeaderFilter, HeaderFormat, HeaderGroups, KmerDump,
+    MissingAbundance, Rounding, RunStats, Smoothing, Spectrum, SpectrumBuilder, MAX_K,
+};
 
 /// Command-line arguments
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+#[command(group(ArgGroup::new("split").args(["per_input", "group_by"])))]
 struct Args {
-    /// Input FASTA file (supports `.zst` compressed files)
-    fasta_file: String,
-    /// k-mer size (maximum 32 for 64-bit representation)
-    k: usize,
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    spectrum: SpectrumArgs,
+    /// Compute one spectrum per input file instead of a single merged spectrum
+    #[arg(long, requires = "output_dir")]
+    per_input: bool,
+    /// Compute one spectrum per value of this Logan header column (`accession`, `counter`, `ka`, `links` or `fN`, the N-th trailing numeric field) instead of a single merged spectrum, reading the inputs once and counting in memory on a single thread
+    #[arg(long, requires = "output_dir", conflicts_with_all = ["threads", "max_memory", "graph_stats"])]
+    group_by: Option<HeaderColumn>,
+    /// Optional: Group the values of the --group-by column into bins of this width, which `counter` and `ka` need, as well as trailing fields with fractional values
+    #[arg(long, requires = "group_by")]
+    group_width: Option<f64>,
+    /// Directory receiving the per-input spectra, written as `<input name>.<format>`, or the per-group spectra, written as `<accession>.<format>` or `<column>_<value>.<format>` (the lower bound of the bin with --group-width)
+    #[arg(long, requires = "split")]
+    output_dir: Option<PathBuf>,
+    /// Optional: File receiving the spectrum instead of the standard output
+    #[arg(short, long, conflicts_with = "split")]
+    output: Option<PathBuf>,
+    /// Output format of the spectrum
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Tsv)]
+    format: OutputFormat,
     /// Optional: Maximum frequency to display
     #[arg(short, long)]
     limit: Option<u64>,
+    /// Optional: File receiving every k-mer and its count, sorted by k-mer (counted in memory, without the fast path)
+    #[arg(long, conflicts_with_all = ["per_input", "group_by", "max_memory"])]
+    dump: Option<PathBuf>,
+    /// Format of the k-mer dump: `binary` can be queried with the `query` subcommand, `text` has `KMER\tCOUNT` lines
+    #[arg(long, value_enum, default_value_t = DumpFormat::Binary, requires = "dump")]
+    dump_format: DumpFormat,
+}
+
+/// Subcommands working on the spectrum instead of printing it
+#[derive(Subcommand)]
+enum Command {
+    /// Fit a GenomeScope-style model on the spectrum, estimating the genome size, heterozygosity, error rate, repeat content and haploid coverage
+    Model(ModelArgs),
+    /// Find the error valley and the coverage peaks of the spectrum, and suggest solid k-mer thresholds
+    Peaks(PeaksArgs),
+    /// Profile the counts of the k-mers of each record (min, median, max, fraction of solid k-mers), e.g. to flag chimeric or contaminant contigs
+    Profile(ProfileArgs),
+    /// Look up the counts of k-mers in a binary dump written with --dump
+    Query(QueryArgs),
+    /// Merge saved spectra: sum histograms bin by bin, or the k-mer counts of binary dumps before computing their spectrum
+    Merge(MergeArgs),
+    /// Compare two saved spectra (histograms or binary dumps), bin by bin and with distances between their distributions
+    Compare(CompareArgs),
+    /// Convert unitigs or contigs and the links of their headers (`L:+:12:-`) into a GFA1 graph, e.g. for Bandage
+    ToGfa(ToGfaArgs),
+}
+
+/// Arguments of the `model` subcommand
+#[derive(clap::Args)]
+struct ModelArgs {
+    #[command(flatten)]
+    spectrum: SpectrumArgs,
+    /// Optional: File receiving the estimates and the fitted spectrum instead of the standard output
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Output format of the estimates and the fitted spectrum (`histo` only holds spectra)
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Tsv)]
+    format: OutputFormat,
+    /// Highest k-mer frequency the model is fitted on
+    #[arg(long, default_value_t = 1000.0)]
+    max_frequency: f64,
+}
+
+/// Arguments of the `peaks` subcommand
+#[derive(clap::Args)]
+struct PeaksArgs {
+    #[command(flatten)]
+    spectrum: SpectrumArgs,
+    /// Analyze the spectrum of each input file instead of a single merged spectrum, writing one row per input
+    #[arg(long)]
+    per_input: bool,
+    /// Optional: File receiving the peaks instead of the standard output
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Output format of the peaks (`histo` only holds spectra)
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Tsv)]
+    format: OutputFormat,
+    #[command(flatten)]
+    peaks: PeakArgs,
+}
+
+/// Arguments finding the valley and the peaks of a spectrum, shared by `peaks` and `profile`
+#[derive(clap::Args)]
+struct PeakArgs {
+    /// Smoothing of the spectrum before looking for its valley and peaks
+    #[arg(long, value_enum, default_value_t = Smoothing::Average)]
+    smoothing: Smoothing,
+    /// Number of bins of the smoothing window
+    #[arg(long, default_value_t = 3)]
+    window: usize,
+    /// Minimum prominence of a peak, relative to the height of the highest one
+    #[arg(long, default_value_t = 0.05)]
+    min_prominence: f64,
+}
+
+/// Arguments of the `profile` subcommand
+#[derive(clap::Args)]
+struct ProfileArgs {
+    #[command(flatten)]
+    spectrum: SpectrumArgs,
+    /// Optional: Lowest count of solid k-mers (default: the error valley of the spectrum, as found by `peaks`)
+    #[arg(long)]
+    solid: Option<f64>,
+    #[command(flatten)]
+    peaks: PeakArgs,
+    /// Optional: File receiving the profiles instead of the standard output
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Output format of the profiles (`histo` only holds spectra)
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Tsv)]
+    format: OutputFormat,
+}
+
+/// Arguments of the `query` subcommand
+#[derive(clap::Args)]
+struct QueryArgs {
+    /// Binary k-mer dump written with --dump
+    dump: PathBuf,
+    /// FASTA/FASTQ file (optionally compressed), or list of k-mers one per line, whose k-mers are looked up, or `-` for the standard input
+    queries: PathBuf,
+    /// Optional: File receiving the counts instead of the standard output
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+/// Arguments of the `merge` subcommand
+#[derive(clap::Args)]
+struct MergeArgs {
+    /// Histograms written in any format, or binary k-mer dumps written with --dump
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+    /// Optional: Width of the bins of histograms that do not say it (TSV, CSV, histo), or of the merged spectrum of dumps
+    #[arg(long)]
+    bin_width: Option<f64>,
+    /// Optional: File receiving the merged spectrum instead of the standard output
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Output format of the merged spectrum
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Tsv)]
+    format: OutputFormat,
+    /// Optional: Maximum frequency to display
+    #[arg(short, long)]
+    limit: Option<u64>,
+}
+
+/// Arguments of the `compare` subcommand
+#[derive(clap::Args)]
+struct CompareArgs {
+    /// First histogram or binary k-mer dump
+    first: PathBuf,
+    /// Second histogram or binary k-mer dump
+    second: PathBuf,
+    /// Optional: Width of the bins of histograms that do not say it (TSV, CSV, histo), or of the spectra of dumps
+    #[arg(long)]
+    bin_width: Option<f64>,
+    /// Optional: File receiving the comparison instead of the standard output
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Output format of the comparison (`histo` only holds spectra)
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Tsv)]
+    format: OutputFormat,
+}
+
+/// Arguments of the `to-gfa` subcommand
+#[derive(clap::Args)]
+struct ToGfaArgs {
+    /// Input FASTA file of unitigs or contigs (optionally zstd, gzip, bzip2 or xz compressed), or `-` for the standard input
+    input: PathBuf,
+    /// Optional: File receiving the GFA graph instead of the standard output
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    #[command(flatten)]
+    header: HeaderArgs,
+}
+
+/// Arguments describing how the abundances are read from the FASTA headers of an assembly
+#[derive(clap::Args)]
+struct HeaderArgs {
+    /// Assembler whose FASTA headers carry the abundances
+    #[arg(long, value_enum, default_value_t = HeaderFormat::Logan)]
+    header_format: HeaderFormat,
+    /// Optional: SAM-style tag of the abundance in FASTA headers (e.g. `KC` for `KC:i:42`), instead of --header-format
+    #[arg(long, value_parser = HeaderField::from_tag, conflicts_with = "abundance_regex")]
+    abundance_tag: Option<HeaderField>,
+    /// Optional: Regular expression matching the abundance in FASTA headers, in its first capture group if any (e.g. `depth=([0-9.]+)`), instead of --header-format
+    #[arg(long, value_parser = HeaderField::from_regex)]
+    abundance_regex: Option<HeaderField>,
+    /// Rounding applied to fractional header abundances (e.g. `ka:f:7.304`)
+    #[arg(long, value_enum, default_value_t = Rounding::Float)]
+    rounding: Rounding,
+    /// Optional: Skip the records whose abundance (after rounding) is below this value
+    #[arg(long)]
+    min_abundance: Option<f64>,
+    /// Optional: Skip the records whose abundance (after rounding) is above this value
+    #[arg(long)]
+    max_abundance: Option<f64>,
+    /// k used to build the input unitigs or contigs (31 for Logan), whose k-1 bases overlap between linked sequences
+    #[arg(long, default_value_t = 31)]
+    assembly_k: usize,
+}
+
+/// Arguments describing the inputs and how their spectrum is computed
+#[derive(clap::Args)]
+struct SpectrumArgs {
+    /// Input FASTA/FASTQ files, directories or glob patterns (optionally zstd, gzip, bzip2 or xz compressed), or `-` for the standard input, followed by the k-mer size (maximum 256)
+    #[arg(value_name = "INPUTS... K", required = true)]
+    positionals: Vec<String>,
+    /// Input FASTA/FASTQ files, directories or glob patterns
+    #[arg(skip)]
+    inputs: Vec<String>,
+    /// k-mer size
+    #[arg(skip)]
+    k: usize,
+    /// Optional: File listing input files, one per line
+    #[arg(long)]
+    fof: Option<PathBuf>,
     /// Optional: Consider all k-mers as canonical
     #[arg(long)]
     canonical: bool,
+    #[command(flatten)]
+    header: HeaderArgs,
+    /// What to do with FASTA records without abundance: `skip`, `error`, or an abundance to use instead
+    #[arg(long, default_value_t = MissingAbundance::Skip)]
+    missing_abundance: MissingAbundance,
+    /// Optional: Stop on the first FASTA record whose abundance is not a number, instead of handling it as a missing abundance
+    #[arg(long)]
+    strict: bool,
+    /// Optional: Drop the k-mers whose weighted count is below this value, from the spectrum and the dump
+    #[arg(long)]
+    min_count: Option<f64>,
+    /// Optional: Drop the k-mers whose weighted count is above this value, from the spectrum and the dump
+    #[arg(long)]
+    max_count: Option<f64>,
+    /// Optional: Skip the records shorter than this length
+    #[arg(long)]
+    min_length: Option<usize>,
+    /// Optional: Skip the records longer than this length
+    #[arg(long)]
+    max_length: Option<usize>,
+    /// Optional: Only count the records whose Logan header satisfies this condition on a column (`accession`, `counter`, `ka`, `links` or `fN`, the N-th trailing numeric field), e.g. `f2<0.1` or `links>=1`; may be repeated
+    #[arg(long = "filter", value_name = "CONDITION")]
+    filters: Vec<HeaderFilter>,
+    /// Ignore the first and last N bases of each sequence, e.g. the k-1 bases of overlap between neighbouring unitigs
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    trim: usize,
+    /// How the abundances of the occurrences of a k-mer are combined into its count
+    #[arg(long, value_enum, default_value_t = Aggregation::Sum)]
+    aggregate: Aggregation,
+    /// Optional: Bin k-mer counts into buckets of this width instead of rounding them to integers
+    #[arg(long)]
+    bin_width: Option<f64>,
+    /// Number of worker threads extracting and counting k-mers
+    #[arg(short, long, default_value_t = 1)]
+    threads: usize,
+    /// Optional: Count k-mers through temporary bucket files, keeping each in-memory table under this size (e.g. `16G`)
+    #[arg(long, value_parser = parse_size, conflicts_with = "threads")]
+    max_memory: Option<usize>,
+    /// Directory of the temporary bucket files used with `--max-memory` (default: system temporary directory)
+    #[arg(long, requires = "max_memory")]
+    tmp_dir: Option<PathBuf>,
+    /// Build the spectrum from sequence lengths only, assuming each k-mer occurs in a single sequence
+    #[arg(long, value_enum, default_value_t = FastPath::Auto)]
+    fast_path: FastPath,
+    /// Optional: Count the k-mers of each junction between unitigs linked in the headers (`L:+:12:-`) once, in the unitig of smallest id, instead of once per unitig (for k below --assembly-k, requires --canonical)
+    #[arg(long, requires = "canonical")]
+    dedup_junctions: bool,
+    /// Optional: Print statistics of the records and k-mers read to the standard error
+    #[arg(long)]
+    stats: bool,
+    /// Optional: File receiving the statistics of the records and k-mers read, as JSON
+    #[arg(long)]
+    stats_json: Option<PathBuf>,
+    /// Optional: Print statistics of the graph of the unitigs linked in the headers (degrees, tips, bubbles) to the standard error, and add them to --stats-json
+    #[arg(long)]
+    graph_stats: bool,
 }
 
-/// Extracts abundance from FASTA header following `[accession]_[counter] ka:f:[abundance]`
-fn extract_abundance(header: &str) -> Option<u32> {
-    let re = Regex::new(r"ka:f:(\d+)").unwrap();
-    re.captures(header).and_then(|caps| caps.get(1)).map(|m| m.as_str().parse().ok()).flatten()
+/// Parses a memory size such as `512M` or `16G` (binary units, optional `B`/`iB` suffix)
+fn parse_size(size: &str) -> Result<usize, String> {
+    let size = size.trim();
+    let digits = size.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit = size[digits.len()..].to_ascii_uppercase();
+    let multiplier: usize = match unit.trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(format!("unknown size unit `{}`", unit)),
+    };
+    let value: f64 = digits
+        .parse()
+        .map_err(|_| format!("invalid size `{}`", size))?;
+    Ok((value * multiplier as f64) as usize)
 }
 
-/// Convert a nucleotide to its 2-bit representation
-const fn nucleotide_to_bits(n: u8) -> Option<u64> {
-    match n {
-        b'A' | b'a' => Some(0b00),
-        b'C' | b'c' => Some(0b01),
-        b'G' | b'g' => Some(0b10),
-        b'T' | b't' => Some(0b11),
-        _ => None,
+/// Parses the command line, splitting the positional arguments into the inputs and k
+fn parse_args() -> Args {
+    let mut args = Args::parse();
+    match &mut args.command {
+        Some(Command::Model(model)) => split_positionals(&mut model.spectrum),
+        Some(Command::Peaks(peaks)) => split_positionals(&mut peaks.spectrum),
+        Some(Command::Profile(profile)) => split_positionals(&mut profile.spectrum),
+        Some(Command::Query(_) | Command::Merge(_) | Command::Compare(_) | Command::ToGfa(_)) => {}
+        None => split_positionals(&mut args.spectrum),
     }
+    args
 }
 
-/// Convert a nucleotide to its complement's 2-bit representation
-const fn complement_to_bits(n: u8) -> Option<u64> {
-    match n {
-        b'A' | b'a' => Some(0b11), // T
-        b'C' | b'c' => Some(0b10), // G
-        b'G' | b'g' => Some(0b01), // C
-        b'T' | b't' => Some(0b00), // A
-        _ => None,
+/// Splits the positional arguments into the inputs and k, which comes last
+fn split_positionals(args: &mut SpectrumArgs) {
+    let k = args.positionals.pop().unwrap_or_default();
+    args.k = k.parse().unwrap_or_else(|_| {
+        Args::command()
+            .error(
+                ErrorKind::ValueValidation,
+                format!("invalid k-mer size `{}`", k),
+            )
+            .exit()
+    });
+    args.inputs = std::mem::take(&mut args.positionals);
+    if args.inputs.is_empty() && args.fof.is_none() {
+        Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "no input file given before k (or with --fof)",
+            )
+            .exit()
     }
 }
 
-/// Encodes a DNA sequence into a 64-bit integer
-fn encode_kmer(seq: &[u8], k: usize) -> Option<u64> {
-    if k > 32 || seq.len() < k {
-        return None;
+fn main() -> io::Result<()> {
+    let args = parse_args();
+    match &args.command {
+        Some(Command::Model(model)) => return run_model(model),
+        Some(Command::Peaks(peaks)) => return run_peaks(peaks),
+        Some(Command::Profile(profile)) => return run_profile(profile),
+        Some(Command::Query(query)) => return run_query(query),
+        Some(Command::Merge(merge)) => return run_merge(merge),
+        Some(Command::Compare(compare)) => return run_compare(compare),
+        Some(Command::ToGfa(to_gfa)) => return run_to_gfa(to_gfa),
+        None => {}
     }
-    
-    let mut encoded: u64 = 0;
-    for i in 0..k {
-        if let Some(bits) = nucleotide_to_bits(seq[i]) {
-            encoded = (encoded << 2) | bits;
-        } else {
-            return None; // Invalid nucleotide
+
+    check_histo_bin_width(args.format, args.spectrum.bin_width);
+    let dump = args.dump.clone().map(|path| (path, args.dump_format));
+    let builder = spectrum_builder(&args.spectrum).dump(dump);
+    let inputs = expand_spectrum_inputs(&args.spectrum)?;
+    let mut stats = Vec::new();
+
+    if let (Some(output_dir), Some(column)) = (&args.output_dir, args.group_by) {
+        let groups = HeaderGroups::new(column, args.group_width).unwrap_or_else(|e| {
+            eprintln!("Error: --group-by {}: {}", column, e);
+            std::process::exit(1);
+        });
+        let spectra = builder.build_groups_from_paths(&inputs, &groups)?;
+        let names: Vec<String> = spectra
+            .iter()
+            .map(|(group, _, _)| groups.name(group))
+            .collect();
+        // Accessions come from the headers, and must not escape the output directory
+        if let Some(name) = names
+            .iter()
+            .find(|name| matches!(name.as_str(), "" | "." | "..") || name.contains(['/', '\\']))
+        {
+            eprintln!("Error: accession `{}` cannot name an output file", name);
+            std::process::exit(1);
+        }
+
+        fs::create_dir_all(output_dir)?;
+        for ((_, spectrum, run_stats), name) in spectra.into_iter().zip(names) {
+            let path = output_dir.join(format!("{}.{}", name, args.format.extension()));
+            save_spectrum(&path, &spectrum, &args, &inputs)?;
+            if wants_stats(&args.spectrum) {
+                report_stats(&args.spectrum, name, run_stats, &mut stats);
+            }
+        }
+    } else if let Some(output_dir) = &args.output_dir {
+        let mut outputs: Vec<_> = inputs.iter().map(|input| input_stem(input)).collect();
+        outputs.sort();
+        if let Some(name) = outputs.windows(2).find(|pair| pair[0] == pair[1]) {
+            eprintln!(
+                "Error: several inputs would be written to {}.{}",
+                name[0],
+                args.format.extension()
+            );
+            std::process::exit(1);
+        }
+
+        fs::create_dir_all(output_dir)?;
+        for input in &inputs {
+            let inputs = std::slice::from_ref(input);
+            let spectrum = build_spectrum(&builder, &args.spectrum, inputs, &mut stats)?;
+            let path =
+                output_dir.join(format!("{}.{}", input_stem(input), args.format.extension()));
+            save_spectrum(&path, &spectrum, &args, inputs)?;
+        }
+    } else {
+        let spectrum = build_spectrum(&builder, &args.spectrum, &inputs, &mut stats)?;
+        match &args.output {
+            Some(path) => save_spectrum(path, &spectrum, &args, &inputs)?,
+            None => write_histogram(
+                &mut io::stdout().lock(),
+                &spectrum,
+                args.format,
+                args.limit,
+                run_metadata(&args.spectrum, &inputs),
+            )?,
         }
     }
-    Some(encoded)
+
+    save_stats(&args.spectrum, &inputs, &stats)
 }
 
-/// Encodes the reverse complement of a DNA sequence
-fn encode_reverse_complement(seq: &[u8], k: usize) -> Option<u64> {
-    if k > 32 || seq.len() < k {
-        return None;
+/// Fits the model on the spectrum of all inputs and writes its estimates and curve
+fn run_model(args: &ModelArgs) -> io::Result<()> {
+    if args.max_frequency.is_nan() || args.max_frequency < 1.0 {
+        eprintln!("Error: --max-frequency must be at least 1");
+        std::process::exit(1);
     }
-    
-    let mut encoded: u64 = 0;
-    for i in (0..k).rev() {
-        if let Some(bits) = complement_to_bits(seq[i]) {
-            encoded = (encoded << 2) | bits;
-        } else {
-            return None; // Invalid nucleotide
+    check_not_histo(args.format, "model");
+    let builder = spectrum_builder(&args.spectrum);
+    let inputs = expand_spectrum_inputs(&args.spectrum)?;
+    let mut stats = Vec::new();
+    let spectrum = build_spectrum(&builder, &args.spectrum, &inputs, &mut stats)?;
+    save_stats(&args.spectrum, &inputs, &stats)?;
+
+    let Some(fit) = fit_model(&spectrum, args.spectrum.k, args.max_frequency) else {
+        eprintln!(
+            "Error: the spectrum has too few frequencies above its error k-mers to fit the model"
+        );
+        std::process::exit(1);
+    };
+    let metadata = run_metadata(&args.spectrum, &inputs);
+    match &args.output {
+        Some(path) => {
+            let mut output = BufWriter::new(File::create(path)?);
+            write_model(&mut output, &fit, args.format, metadata)?;
+            output.flush()
         }
+        None => write_model(&mut io::stdout().lock(), &fit, args.format, metadata),
     }
-    Some(encoded)
 }
 
-/// Generates k-mers as bit-encoded u64 values, considering canonical representation if required
-fn generate_encoded_kmers(seq: &[u8], k: usize, canonical: bool) -> Vec<u64> {
-    if seq.len() < k {
-        return vec![];
+/// Finds the valley and peaks of the merged spectrum of all inputs, or of each input's one
+fn run_peaks(args: &PeaksArgs) -> io::Result<()> {
+    check_peak_args(&args.peaks);
+    check_not_histo(args.format, "peaks");
+    let builder = spectrum_builder(&args.spectrum);
+    let inputs = expand_spectrum_inputs(&args.spectrum)?;
+    let mut stats = Vec::new();
+
+    let find = |spectrum: &Spectrum| {
+        let peaks = &args.peaks;
+        find_peaks(
+            spectrum,
+            peaks.smoothing,
+            peaks.window,
+            peaks.min_prominence,
+        )
+    };
+    let spectra = if args.per_input {
+        inputs
+            .iter()
+            .map(|input| {
+                let inputs = std::slice::from_ref(input);
+                let spectrum = build_spectrum(&builder, &args.spectrum, inputs, &mut stats)?;
+                Ok((input_stem(input), find(&spectrum)))
+            })
+            .collect::<io::Result<Vec<_>>>()?
+    } else {
+        let spectrum = build_spectrum(&builder, &args.spectrum, &inputs, &mut stats)?;
+        vec![(spectrum_name(&inputs), find(&spectrum))]
+    };
+    save_stats(&args.spectrum, &inputs, &stats)?;
+
+    let metadata = run_metadata(&args.spectrum, &inputs);
+    match &args.output {
+        Some(path) => {
+            let mut output = BufWriter::new(File::create(path)?);
+            write_peaks(&mut output, &spectra, args.format, metadata)?;
+            output.flush()
+        }
+        None => write_peaks(&mut io::stdout().lock(), &spectra, args.format, metadata),
     }
+}
 
-    let mut kmers: Vec<u64> = Vec::new();
-    for i in 0..=seq.len() - k {
-        let kmer_slice = &seq[i..i + k];
-        
-        if let Some(encoded) = encode_kmer(kmer_slice, k) {
-            if canonical {
-                if let Some(rev_comp) = encode_reverse_complement(kmer_slice, k) {
-                    kmers.push(std::cmp::min(encoded, rev_comp));
-                }
-            } else {
-                kmers.push(encoded);
-            }
+/// Counts the k-mers of all inputs, then writes the profile of the counts of the k-mers of each
+/// record
+fn run_profile(args: &ProfileArgs) -> io::Result<()> {
+    if args.solid.is_some_and(f64::is_nan) {
+        eprintln!("Error: --solid must be a number");
+        std::process::exit(1);
+    }
+    if args.spectrum.stats || args.spectrum.stats_json.is_some() || args.spectrum.graph_stats {
+        eprintln!("Error: run statistics are not available when profiling records");
+        std::process::exit(1);
+    }
+    check_peak_args(&args.peaks);
+    check_not_histo(args.format, "profile");
+    let builder = spectrum_builder(&args.spectrum);
+    let inputs = expand_spectrum_inputs(&args.spectrum)?;
+    let peaks = &args.peaks;
+    let profiles = builder.profile_paths(
+        &inputs,
+        args.solid,
+        peaks.smoothing,
+        peaks.window,
+        peaks.min_prominence,
+    )?;
+
+    let mut output: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    write_profiles(
+        &mut output,
+        &profiles,
+        args.format,
+        run_metadata(&args.spectrum, &inputs),
+    )?;
+    output.flush()
+}
+
+/// Looks up the counts of the k-mers of the queries in a binary dump, written as `KMER\tCOUNT`
+/// lines in query order (0 for absent k-mers)
+fn run_query(args: &QueryArgs) -> io::Result<()> {
+    let mut dump = KmerDump::open(&args.dump)?;
+    let k = dump.k();
+    let mut output: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    for record in open_sequences_or_list(&args.queries)? {
+        let record = record?;
+        // Windows containing a base other than ACGT are not k-mers
+        let kmers = record
+            .seq()
+            .windows(k)
+            .filter(|kmer| kmer.iter().all(|&n| nucleotide_to_bits(n).is_some()));
+        for kmer in kmers {
+            let count = dump.get(kmer)?.unwrap_or(0.0);
+            output.write_all(kmer)?;
+            writeln!(output, "\t{}", count)?;
         }
     }
-    kmers
+    output.flush()
 }
 
-/// Opens a FASTA file, supporting both regular and `.zst` compressed formats
-fn open_fasta_file(file_path: &Path) -> std::io::Result<Box<dyn Read>> {
-    let file = File::open(file_path)?;
-    if file_path.extension().map_or(false, |ext| ext == "zst") {
-        let decoder = Decoder::new(file)?;
-        Ok(Box::new(BufReader::new(decoder)))
-    } else {
-        Ok(Box::new(BufReader::new(file)))
+/// Merges saved spectra and writes the merged histogram
+fn run_merge(args: &MergeArgs) -> io::Result<()> {
+    check_bin_width(args.bin_width);
+    check_histo_bin_width(args.format, args.bin_width);
+    let spectrum = merge_spectra(&args.inputs, args.bin_width)?;
+    let metadata = saved_metadata(&args.inputs, spectrum.bin_width());
+    match &args.output {
+        Some(path) => {
+            let mut output = BufWriter::new(File::create(path)?);
+            write_histogram(&mut output, &spectrum, args.format, args.limit, metadata)?;
+            output.flush()
+        }
+        None => write_histogram(
+            &mut io::stdout().lock(),
+            &spectrum,
+            args.format,
+            args.limit,
+            metadata,
+        ),
+    }
+}
+
+/// Compares two saved spectra and writes their distances and bin-by-bin differences
+fn run_compare(args: &CompareArgs) -> io::Result<()> {
+    check_bin_width(args.bin_width);
+    check_not_histo(args.format, "compare");
+    let first = load_spectrum(&args.first, args.bin_width)?;
+    let second = load_spectrum(&args.second, args.bin_width)?;
+    let Some(comparison) = compare_spectra(&first, &second) else {
+        eprintln!("Error: the spectra do not have the same bin width");
+        std::process::exit(1);
+    };
+    let metadata = saved_metadata(
+        &[args.first.clone(), args.second.clone()],
+        comparison.bin_width,
+    );
+    match &args.output {
+        Some(path) => {
+            let mut output = BufWriter::new(File::create(path)?);
+            write_comparison(&mut output, &comparison, args.format, metadata)?;
+            output.flush()
+        }
+        None => write_comparison(&mut io::stdout().lock(), &comparison, args.format, metadata),
+    }
+}
+
+/// Writes the sequences of an assembly and the links of their headers as a GFA1 graph
+fn run_to_gfa(args: &ToGfaArgs) -> io::Result<()> {
+    check_header_args(&args.header);
+    let abundance = AbundanceSource::Header(header_field(&args.header), args.header.rounding);
+    let range = args.header.min_abundance.unwrap_or(f64::NEG_INFINITY)
+        ..=args.header.max_abundance.unwrap_or(f64::INFINITY);
+    let records = read_records(std::slice::from_ref(&args.input));
+    let mut output: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    write_gfa(
+        &mut output,
+        records,
+        &abundance,
+        args.header.assembly_k,
+        &range,
+    )?;
+    output.flush()
+}
+
+/// Exits with an error if the abundance bounds or the assembly k of the header arguments are
+/// invalid
+fn check_header_args(args: &HeaderArgs) {
+    check_range(args.min_abundance, args.max_abundance, "abundance");
+    if args.assembly_k == 0 {
+        eprintln!("Error: --assembly-k must be at least 1");
+        std::process::exit(1);
+    }
+}
+
+/// Exits with an error if the bounds given by `--min-<name>` and `--max-<name>` are not numbers
+/// or cross
+fn check_range(min: Option<f64>, max: Option<f64>, name: &str) {
+    if min.or(max).is_some_and(f64::is_nan) || min.zip(max).is_some_and(|(min, max)| min > max) {
+        eprintln!("Error: --min-{0} cannot exceed --max-{0}", name);
+        std::process::exit(1);
+    }
+}
+
+/// Exits with an error if the bin width is not strictly positive
+fn check_bin_width(bin_width: Option<f64>) {
+    if bin_width.is_some_and(|width| !width.is_finite() || width <= 0.0) {
+        eprintln!("Error: --bin-width must be strictly positive");
+        std::process::exit(1);
+    }
+}
+
+/// Checks the spectrum arguments and configures the spectrum computation from them
+fn spectrum_builder(args: &SpectrumArgs) -> SpectrumBuilder {
+    if args.k == 0 || args.k > MAX_K {
+        eprintln!("Error: k-mer size must be between 1 and {}", MAX_K);
+        std::process::exit(1);
+    }
+    check_bin_width(args.bin_width);
+    check_header_args(&args.header);
+    check_range(args.min_count, args.max_count, "count");
+    if args
+        .min_length
+        .zip(args.max_length)
+        .is_some_and(|(min, max)| min > max)
+    {
+        eprintln!("Error: --min-length cannot exceed --max-length");
+        std::process::exit(1);
+    }
+    if args.threads == 0 {
+        eprintln!("Error: --threads must be at least 1");
+        std::process::exit(1);
+    }
+    if args
+        .max_memory
+        .is_some_and(|max_memory| max_memory < 1 << 20)
+    {
+        eprintln!("Error: --max-memory must be at least 1M");
+        std::process::exit(1);
     }
+
+    SpectrumBuilder::new(args.k)
+        .canonical(args.canonical)
+        .abundance(AbundanceSource::Header(
+            header_field(&args.header),
+            args.header.rounding,
+        ))
+        .missing_abundance(args.missing_abundance)
+        .strict(args.strict)
+        .abundance_range(args.header.min_abundance, args.header.max_abundance)
+        .count_range(args.min_count, args.max_count)
+        .length_range(args.min_length, args.max_length)
+        .trim(args.trim)
+        .header_filters(args.filters.clone())
+        .aggregation(args.aggregate)
+        .bin_width(args.bin_width)
+        .threads(args.threads)
+        .max_memory(args.max_memory)
+        .tmp_dir(args.tmp_dir.clone())
+        .assembly_k(args.header.assembly_k)
+        .fast_path(args.fast_path)
+        .dedup_junctions(args.dedup_junctions)
+        .graph_stats(args.graph_stats)
 }
 
-fn main() -> std::io::Result<()> {
-    let args = Args::parse();
-    
-    if args.k > 32 {
-        eprintln!("Error: k-mer size cannot exceed 32 for the 64-bit representation");
+/// Exits with an error if a histogram binned with a width other than 1 is written as `histo`,
+/// whose k-mer counts are integers
+fn check_histo_bin_width(format: OutputFormat, bin_width: Option<f64>) {
+    if format == OutputFormat::Histo && bin_width.is_some_and(|width| width != 1.0) {
+        eprintln!("Error: --format histo needs integer k-mer counts, and cannot be used with a --bin-width other than 1");
         std::process::exit(1);
     }
-    
-    let fasta_path = Path::new(&args.fasta_file);
-    let fasta_reader = open_fasta_file(fasta_path)?;
-    let reader = fasta::Reader::new(fasta_reader);
-    
-    let mut kmer_counts: HashMap<u64, u64> = HashMap::new();
+}
+
+/// Exits with an error if the smoothing window or the minimum prominence of the peak arguments
+/// are invalid
+fn check_peak_args(args: &PeakArgs) {
+    if args.window == 0 {
+        eprintln!("Error: --window must be at least 1");
+        std::process::exit(1);
+    }
+    if args.min_prominence.is_nan() || args.min_prominence < 0.0 {
+        eprintln!("Error: --min-prominence must be positive");
+        std::process::exit(1);
+    }
+}
 
-    for result in reader.records() {
-        let record = result?;
-        let header = format!(
-            "{} {}", 
-            record.id(), 
-            record.desc().unwrap_or("")
+/// Exits with an error if a subcommand writing something else than a spectrum is asked for
+/// `histo`, which only holds spectra
+fn check_not_histo(format: OutputFormat, command: &str) {
+    if format == OutputFormat::Histo {
+        eprintln!(
+            "Error: --format histo only holds spectra, and cannot be used with {}",
+            command
         );
-        let sequence = record.seq();
-        
-        if let Some(abundance) = extract_abundance(&header) {
-            let kmers = generate_encoded_kmers(sequence, args.k, args.canonical);
-            for kmer in kmers {
-                *kmer_counts.entry(kmer).or_insert(0) += abundance as u64;
-            }
-        }
+        std::process::exit(1);
+    }
+}
+
+/// Computes the spectrum of `inputs`, collecting its run statistics into `stats` if requested
+fn build_spectrum(
+    builder: &SpectrumBuilder,
+    args: &SpectrumArgs,
+    inputs: &[PathBuf],
+    stats: &mut Vec<(String, RunStats)>,
+) -> io::Result<Spectrum> {
+    if !wants_stats(args) {
+        return builder.build_from_paths(inputs);
+    }
+    let (spectrum, run_stats) = builder.build_from_paths_with_stats(inputs)?;
+    report_stats(args, spectrum_name(inputs), run_stats, stats);
+    Ok(spectrum)
+}
+
+/// Whether run statistics are printed or saved
+fn wants_stats(args: &SpectrumArgs) -> bool {
+    args.stats || args.stats_json.is_some() || args.graph_stats
+}
+
+/// Prints the run statistics of the spectrum named `name` if requested, and collects them into
+/// `stats`
+fn report_stats(
+    args: &SpectrumArgs,
+    name: String,
+    run_stats: RunStats,
+    stats: &mut Vec<(String, RunStats)>,
+) {
+    if args.stats {
+        eprintln!("Statistics of {}:\n{}", name, run_stats);
+    }
+    if let Some(graph) = run_stats.graph.as_ref().filter(|_| args.graph_stats) {
+        eprintln!("Graph statistics of {}:\n{}", name, graph);
     }
+    stats.push((name, run_stats));
+}
 
-    // Compute histogram
-    let mut histogram: HashMap<u64, u64> = HashMap::new();
-    for &count in kmer_counts.values() {
-        *histogram.entry(count).or_insert(0) += 1;
+/// Writes the run statistics of the spectra into the file given by `--stats-json`, if any
+fn save_stats(
+    args: &SpectrumArgs,
+    inputs: &[PathBuf],
+    stats: &[(String, RunStats)],
+) -> io::Result<()> {
+    let Some(path) = &args.stats_json else {
+        return Ok(());
+    };
+    let mut output = BufWriter::new(File::create(path)?);
+    write_stats(&mut output, stats, run_metadata(args, inputs))?;
+    output.flush()
+}
+
+/// Name of the spectrum of `inputs`: the name of the input if there is a single one
+fn spectrum_name(inputs: &[PathBuf]) -> String {
+    match inputs {
+        [input] => input_stem(input),
+        _ => "merged".to_string(),
     }
+}
+
+/// Field of the FASTA headers holding the abundances: the given tag or regular expression, or
+/// the one of the header format
+fn header_field(args: &HeaderArgs) -> HeaderField {
+    args.abundance_tag
+        .clone()
+        .or_else(|| args.abundance_regex.clone())
+        .unwrap_or_else(|| HeaderField::preset(args.header_format))
+}
 
-    // Sort the histogram for printing
-    let mut sorted_histogram: Vec<_> = histogram.iter().collect();
-    sorted_histogram.sort();
+/// Expands the inputs and the file-of-filenames into the list of input files
+fn expand_spectrum_inputs(args: &SpectrumArgs) -> io::Result<Vec<PathBuf>> {
+    let inputs = expand_inputs(&args.inputs, args.fof.as_deref())?;
+    if inputs.is_empty() {
+        eprintln!("Error: no input file");
+        std::process::exit(1);
+    }
+    Ok(inputs)
+}
 
-    // Print header
-    println!("K-mer Frequency\tCount");
+/// Writes the spectrum of `inputs` into a file, in the format chosen by `--format`
+fn save_spectrum(
+    path: &Path,
+    spectrum: &Spectrum,
+    args: &Args,
+    inputs: &[PathBuf],
+) -> io::Result<()> {
+    let mut output = BufWriter::new(File::create(path)?);
+    write_histogram(
+        &mut output,
+        spectrum,
+        args.format,
+        args.limit,
+        run_metadata(&args.spectrum, inputs),
+    )?;
+    output.flush()
+}
 
-    // Print histogram, applying the optional `--limit`
-    for (freq, count) in sorted_histogram {
-        if let Some(limit) = args.limit {
-            if *freq > limit {
-                break;
-            }
-        }
-        println!("{}\t{}", freq, count);
+/// Run metadata written along with the histogram in the JSON format
+fn run_metadata(args: &SpectrumArgs, inputs: &[PathBuf]) -> Map<String, Value> {
+    let inputs: Vec<_> = inputs
+        .iter()
+        .map(|input| input.display().to_string())
+        .collect();
+    let rounding = args
+        .header
+        .rounding
+        .to_possible_value()
+        .map(|value| value.get_name().to_string());
+    let aggregate = args
+        .aggregate
+        .to_possible_value()
+        .map(|value| value.get_name().to_string());
+    let metadata = json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "inputs": inputs,
+        "k": args.k,
+        "canonical": args.canonical,
+        "abundance": header_field(&args.header).to_string(),
+        "missing_abundance": args.missing_abundance.to_string(),
+        "strict": args.strict,
+        "rounding": rounding,
+        "min_abundance": args.header.min_abundance,
+        "max_abundance": args.header.max_abundance,
+        "aggregate": aggregate,
+        "min_count": args.min_count,
+        "max_count": args.max_count,
+        "min_length": args.min_length,
+        "max_length": args.max_length,
+        "trim": args.trim,
+        "filters": args.filters.iter().map(|filter| filter.to_string()).collect::<Vec<_>>(),
+        "dedup_junctions": args.dedup_junctions,
+        "bin_width": args.bin_width,
+    });
+    match metadata {
+        Value::Object(map) => map,
+        _ => unreachable!(),
     }
+}
 
-    Ok(())
-}
\ No newline at end of file
+/// Metadata written along with spectra computed from saved ones in the JSON format
+fn saved_metadata(inputs: &[PathBuf], bin_width: Option<f64>) -> Map<String, Value> {
+    let inputs: Vec<_> = inputs
+        .iter()
+        .map(|input| input.display().to_string())
+        .collect();
+    let metadata = json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "inputs": inputs,
+        "bin_width": bin_width,
+    });
+    match metadata {
+        Value::Object(map) => map,
+        _ => unreachable!(),
+    }
+}
@@ -0,0 +1,402 @@
+//! GenomeScope-style model of a k-mer spectrum
+//!
+//! The spectrum of a diploid genome is fitted, above the error k-mers, with the GenomeScope 1.0
+//! mixture of four negative binomial distributions centred on 1, 2, 3 and 4 times the average
+//! count of a k-mer from a single haplotype. Their weights depend on the heterozygosity and on
+//! the rate of duplicated sequence; the genome size, the error rate and the repeat content are
+//! then derived from the fitted curve and the observed k-mers.
+
+use crate::peaks::first_minimum;
+use crate::spectrum::Spectrum;
+
+/// Number of Nelder-Mead iterations of each fit
+const ITERATIONS: usize = 2000;
+
+/// Number of histogram bins needed above the error k-mers to fit the model
+const MIN_BINS: usize = 8;
+
+/// Observed and fitted number of k-mers of a histogram bin
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CurvePoint {
+    /// k-mer count of the bin (middle of the bin with a bin width)
+    pub frequency: f64,
+    /// Number of k-mers of the spectrum in the bin
+    pub observed: u64,
+    /// Number of k-mers predicted by the model in the bin
+    pub fitted: f64,
+}
+
+/// Genome characteristics estimated by fitting the model on a spectrum
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModelFit {
+    /// Haploid genome size, in bases
+    pub genome_size: f64,
+    /// Rate of heterozygous sites between the two haplotypes
+    pub heterozygosity: f64,
+    /// Rate of sequencing errors per base
+    pub error_rate: f64,
+    /// Fraction of the genome size not explained by unique or duplicated sequence
+    pub repeat_content: f64,
+    /// Average count of a k-mer occurring in a single haplotype
+    pub haploid_coverage: f64,
+    /// Rate of duplicated sequence of the model
+    pub duplication: f64,
+    /// Overdispersion of the negative binomial distributions (0 for Poisson ones)
+    pub overdispersion: f64,
+    /// Smallest k-mer count the model was fitted on, below which k-mers are errors
+    pub min_frequency: f64,
+    /// Observed and fitted spectrum, by increasing frequency
+    pub curve: Vec<CurvePoint>,
+}
+
+/// Parameters of the mixture of negative binomial distributions
+#[derive(Clone, Copy, Debug)]
+struct Params {
+    heterozygosity: f64,
+    duplication: f64,
+    coverage: f64,
+    overdispersion: f64,
+}
+
+impl Params {
+    /// Maps unconstrained optimizer coordinates onto valid parameters
+    fn from_coordinates(coordinates: &[f64]) -> Self {
+        Params {
+            heterozygosity: logistic(coordinates[0]),
+            duplication: logistic(coordinates[1]),
+            coverage: coordinates[2].exp(),
+            overdispersion: coordinates[3].exp(),
+        }
+    }
+
+    /// Unconstrained optimizer coordinates of the parameters
+    fn coordinates(&self) -> Vec<f64> {
+        vec![
+            logit(self.heterozygosity),
+            logit(self.duplication),
+            self.coverage.ln(),
+            self.overdispersion.ln(),
+        ]
+    }
+
+    /// Probability of a k-mer of the genome to have the count `x`, up to a constant factor
+    ///
+    /// The weights of the four peaks are those of GenomeScope 1.0, from the probabilities `s0`
+    /// and `s1` of a k-mer to cover no heterozygous site or at least one.
+    fn density(&self, k: usize, x: f64) -> f64 {
+        let d = self.duplication;
+        let s0 = (1.0 - self.heterozygosity).powi(k as i32);
+        let s1 = 1.0 - s0;
+        let weights = [
+            (1.0 - d) * 2.0 * s1 + d * (2.0 * s0 * s1 + 2.0 * s1.powi(2)),
+            (1.0 - d) * s0 + d * s1.powi(2),
+            d * 2.0 * s0 * s1,
+            d * s0.powi(2),
+        ];
+        weights
+            .iter()
+            .zip(1..)
+            .map(|(weight, copies)| {
+                let mean = self.coverage * copies as f64;
+                weight * negative_binomial(x, mean / self.overdispersion, mean)
+            })
+            .sum()
+    }
+}
+
+/// Fits the model on the bins of `spectrum` up to `max_frequency`, from k-mers of size `k`
+///
+/// Returns `None` if the spectrum has too few bins above its error k-mers to be fitted.
+pub fn fit_model(spectrum: &Spectrum, k: usize, max_frequency: f64) -> Option<ModelFit> {
+    let width = spectrum.bin_width().unwrap_or(1.0);
+    let bins = spectrum.dense_bins(Some(max_frequency));
+    // Middle of the bins with a bin width
+    let frequency = |bin: u64| match spectrum.bin_width() {
+        Some(width) => (bin as f64 + 0.5) * width,
+        None => bin as f64,
+    };
+    let frequencies: Vec<f64> = bins.iter().map(|&(bin, _)| frequency(bin)).collect();
+    let observed: Vec<u64> = bins.iter().map(|&(_, kmers)| kmers).collect();
+    let y: Vec<f64> = observed.iter().map(|&kmers| kmers as f64).collect();
+
+    // Error k-mers form a decreasing curve before the first minimum
+    let start = first_minimum(&y).unwrap_or(0);
+    if y.len() - start < MIN_BINS {
+        return None;
+    }
+    let peak = (start..y.len()).fold(start, |best, i| if y[i] > y[best] { i } else { best });
+    if y[peak] == 0.0 {
+        return None;
+    }
+
+    let x = &frequencies[start..];
+    let objective = |coordinates: &[f64]| {
+        let params = Params::from_coordinates(coordinates);
+        let predicted: Vec<f64> = x.iter().map(|&x| width * params.density(k, x)).collect();
+        least_squares(&y[start..], &predicted).1
+    };
+
+    // The main peak is either the heterozygous or the homozygous one
+    let mut best: Option<(Vec<f64>, f64)> = None;
+    for coverage in [frequencies[peak] / 2.0, frequencies[peak]] {
+        for heterozygosity in [0.001, 0.01] {
+            let params = Params {
+                heterozygosity,
+                duplication: 0.01,
+                coverage,
+                overdispersion: 0.5,
+            };
+            let (coordinates, error) = nelder_mead(&objective, params.coordinates());
+            if best
+                .as_ref()
+                .is_none_or(|(_, best_error)| error < *best_error)
+            {
+                best = Some((coordinates, error));
+            }
+        }
+    }
+    let params = Params::from_coordinates(&best?.0);
+
+    let predicted: Vec<f64> = frequencies
+        .iter()
+        .map(|&x| width * params.density(k, x))
+        .collect();
+    let (length, _) = least_squares(&y[start..], &predicted[start..]);
+    let curve: Vec<CurvePoint> = frequencies
+        .iter()
+        .zip(&observed)
+        .zip(&predicted)
+        .map(|((&frequency, &observed), &predicted)| CurvePoint {
+            frequency,
+            observed,
+            fitted: length * predicted,
+        })
+        .collect();
+
+    // k-mers observed in excess of the model below the first minimum are errors
+    let total_kmers: f64 = curve
+        .iter()
+        .map(|point| point.frequency * point.observed as f64)
+        .sum();
+    let error_kmers: f64 = curve[..start]
+        .iter()
+        .map(|point| point.frequency * (point.observed as f64 - point.fitted).max(0.0))
+        .sum();
+    let genome_size = (total_kmers - error_kmers) / (2.0 * params.coverage);
+    let unique_size = length * (1.0 - params.duplication);
+    let error_rate = if total_kmers > 0.0 {
+        1.0 - (1.0 - error_kmers / total_kmers).powf(1.0 / k as f64)
+    } else {
+        0.0
+    };
+
+    Some(ModelFit {
+        genome_size,
+        heterozygosity: params.heterozygosity,
+        error_rate,
+        repeat_content: (1.0 - unique_size / genome_size).clamp(0.0, 1.0),
+        haploid_coverage: params.coverage,
+        duplication: params.duplication,
+        overdispersion: params.overdispersion,
+        min_frequency: frequencies[start],
+        curve,
+    })
+}
+
+/// Best scale of `predicted` to fit `observed`, and its sum of squared residuals
+fn least_squares(observed: &[f64], predicted: &[f64]) -> (f64, f64) {
+    let cross: f64 = observed.iter().zip(predicted).map(|(y, p)| y * p).sum();
+    let square: f64 = predicted.iter().map(|p| p * p).sum();
+    let scale = if square > 0.0 {
+        (cross / square).max(0.0)
+    } else {
+        0.0
+    };
+    let error = observed
+        .iter()
+        .zip(predicted)
+        .map(|(y, p)| (y - scale * p).powi(2))
+        .sum();
+    (scale, error)
+}
+
+/// Minimizes `f` from `start` with the Nelder-Mead simplex method
+fn nelder_mead(f: &impl Fn(&[f64]) -> f64, start: Vec<f64>) -> (Vec<f64>, f64) {
+    let n = start.len();
+    let mut simplex: Vec<(Vec<f64>, f64)> = (0..=n)
+        .map(|i| {
+            let mut point = start.clone();
+            if i < n {
+                point[i] += 0.5;
+            }
+            let value = f(&point);
+            (point, value)
+        })
+        .collect();
+    // Moves the worst point towards or beyond the centroid of the others by `factor`
+    let along = |centroid: &[f64], worst: &[f64], factor: f64| -> Vec<f64> {
+        centroid
+            .iter()
+            .zip(worst)
+            .map(|(c, w)| c + factor * (w - c))
+            .collect()
+    };
+
+    for _ in 0..ITERATIONS {
+        simplex.sort_by(|a, b| a.1.total_cmp(&b.1));
+        let (best, worst) = (simplex[0].1, simplex[n].1);
+        if (worst - best).abs() <= 1e-10 * (best.abs() + 1e-30) {
+            break;
+        }
+        let mut centroid = vec![0.0; n];
+        for (point, _) in &simplex[..n] {
+            for (c, p) in centroid.iter_mut().zip(point) {
+                *c += p / n as f64;
+            }
+        }
+
+        let reflected = along(&centroid, &simplex[n].0, -1.0);
+        let reflected_value = f(&reflected);
+        if reflected_value < best {
+            let expanded = along(&centroid, &simplex[n].0, -2.0);
+            let expanded_value = f(&expanded);
+            simplex[n] = if expanded_value < reflected_value {
+                (expanded, expanded_value)
+            } else {
+                (reflected, reflected_value)
+            };
+        } else if reflected_value < simplex[n - 1].1 {
+            simplex[n] = (reflected, reflected_value);
+        } else {
+            let contracted = along(&centroid, &simplex[n].0, 0.5);
+            let contracted_value = f(&contracted);
+            if contracted_value < worst {
+                simplex[n] = (contracted, contracted_value);
+            } else {
+                // Shrink the simplex towards the best point
+                let best_point = simplex[0].0.clone();
+                for (point, value) in &mut simplex[1..] {
+                    *point = along(&best_point, point, 0.5);
+                    *value = f(point);
+                }
+            }
+        }
+    }
+    simplex.sort_by(|a, b| a.1.total_cmp(&b.1));
+    simplex.swap_remove(0)
+}
+
+/// Negative binomial probability of `x` (extended to real values) with the given size and mean
+fn negative_binomial(x: f64, size: f64, mean: f64) -> f64 {
+    let log_probability = ln_gamma(x + size) - ln_gamma(size) - ln_gamma(x + 1.0)
+        + size * (size / (size + mean)).ln()
+        + x * (mean / (size + mean)).ln();
+    log_probability.exp()
+}
+
+/// Natural logarithm of the gamma function of a positive number (Lanczos approximation)
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        // Γ(x) = Γ(x + 1) / x keeps the approximation in its accurate range
+        return ln_gamma(x + 1.0) - x.ln();
+    }
+    let x = x - 1.0;
+    let t = x + 7.5;
+    let series = COEFFICIENTS[1..]
+        .iter()
+        .zip(1..)
+        .fold(COEFFICIENTS[0], |sum, (c, i)| sum + c / (x + i as f64));
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + series.ln()
+}
+
+/// Maps a real number onto (0, 1)
+fn logistic(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// Inverse of [`logistic`]
+fn logit(p: f64) -> f64 {
+    (p / (1.0 - p)).ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Spectrum of a genome of `size` k-mers following the model, with a decreasing curve of
+    /// error k-mers at low counts
+    fn synthetic(params: &Params, k: usize, size: f64) -> Spectrum {
+        let mut spectrum = Spectrum::new(None);
+        for x in 1..=300 {
+            let x = x as f64;
+            let errors = 4.0 * size * (-1.5 * x).exp();
+            let kmers = (size * params.density(k, x) + errors).round() as u64;
+            if kmers > 0 {
+                spectrum.add(x, kmers);
+            }
+        }
+        spectrum
+    }
+
+    #[test]
+    fn recovers_coverage_and_heterozygosity() {
+        for (heterozygosity, coverage) in [(0.01, 25.0), (0.002, 40.0)] {
+            let params = Params {
+                heterozygosity,
+                duplication: 0.05,
+                coverage,
+                overdispersion: 0.3,
+            };
+            let spectrum = synthetic(&params, 21, 1e6);
+            let fit = fit_model(&spectrum, 21, 1000.0).unwrap();
+            assert!(
+                (fit.haploid_coverage - coverage).abs() < 0.02 * coverage,
+                "coverage {} instead of {}",
+                fit.haploid_coverage,
+                coverage
+            );
+            assert!(
+                (fit.heterozygosity - heterozygosity).abs() < 0.1 * heterozygosity,
+                "heterozygosity {} instead of {}",
+                fit.heterozygosity,
+                heterozygosity
+            );
+            assert!(fit.min_frequency > 1.0 && fit.min_frequency < coverage);
+            assert_eq!(fit.curve[0].frequency, 1.0);
+        }
+    }
+
+    #[test]
+    fn needs_enough_bins_above_the_errors() {
+        assert_eq!(fit_model(&Spectrum::new(None), 21, 1000.0), None);
+
+        let mut spectrum = Spectrum::new(None);
+        for (count, kmers) in [(1.0, 1000), (2.0, 300), (3.0, 400), (4.0, 500), (5.0, 200)] {
+            spectrum.add(count, kmers);
+        }
+        assert_eq!(fit_model(&spectrum, 21, 1000.0), None);
+        // Enough bins, but not below `max_frequency`
+        let spectrum = synthetic(
+            &Params {
+                heterozygosity: 0.01,
+                duplication: 0.05,
+                coverage: 25.0,
+                overdispersion: 0.3,
+            },
+            21,
+            1e6,
+        );
+        assert_eq!(fit_model(&spectrum, 21, 6.0), None);
+    }
+}
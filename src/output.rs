@@ -0,0 +1,545 @@
+//! Output of the histogram as TSV, CSV, JSON or GenomeScope/Jellyfish `histo`, of its peaks,
+//! fitted model, comparison with another one and per-record profiles as TSV, CSV or JSON, and of
+//! the run statistics as JSON
+
+use clap::ValueEnum;
+use serde_json::{json, Map, Value};
+use std::io::{self, Write};
+
+use crate::compare::Comparison;
+use crate::model::ModelFit;
+use crate::peaks::{Extremum, Peaks};
+use crate::profile::Profiles;
+use crate::spectrum::Spectrum;
+use crate::stats::RunStats;
+
+/// Output format of the histogram
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Tab-separated values, with a header line
+    Tsv,
+    /// Comma-separated values, with a header line
+    Csv,
+    /// JSON object with the run metadata, the totals and the histogram
+    Json,
+    /// GenomeScope/Jellyfish `histo`: space-separated values, without header, with integer k-mer
+    /// counts from 1 (spectra binned with a width other than 1 cannot be written as `histo`)
+    Histo,
+}
+
+impl OutputFormat {
+    /// File extension of the format
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Tsv => "tsv",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Json => "json",
+            OutputFormat::Histo => "histo",
+        }
+    }
+
+    /// Error for the outputs other than spectra, which cannot be written as `histo`
+    fn reject_histo(self, output: &str) -> io::Result<()> {
+        if self == OutputFormat::Histo {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("the histo format only holds spectra, not {}", output),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Field separator of the delimited formats, or `None` for JSON
+    fn separator(self) -> Option<&'static str> {
+        match self {
+            OutputFormat::Tsv => Some("\t"),
+            OutputFormat::Csv => Some(","),
+            OutputFormat::Histo => Some(" "),
+            OutputFormat::Json => None,
+        }
+    }
+}
+
+/// Writes the spectrum sorted by frequency, up to the optional `limit` frequency
+///
+/// `metadata` describes the run and is only written in the JSON format, along with the
+/// number of distinct k-mers and the total number of k-mers over the whole spectrum.
+pub fn write_histogram<W: Write>(
+    output: &mut W,
+    spectrum: &Spectrum,
+    format: OutputFormat,
+    limit: Option<u64>,
+    metadata: Map<String, Value>,
+) -> io::Result<()> {
+    if format == OutputFormat::Histo && spectrum.bin_width().is_some_and(|width| width != 1.0) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the histo format needs integer k-mer counts, and cannot be written with a bin width other than 1",
+        ));
+    }
+    let displayed = spectrum
+        .bins()
+        .zip(spectrum.iter())
+        .take_while(|(_, (frequency, _))| limit.is_none_or(|limit| *frequency <= limit as f64));
+
+    match format.separator() {
+        Some(separator) => {
+            if format != OutputFormat::Histo {
+                writeln!(output, "K-mer Frequency{}Count", separator)?;
+            }
+            for ((bin, count), _) in displayed {
+                // Jellyfish counts start at 1, while fractional counts below 0.5 round to 0
+                if format == OutputFormat::Histo && bin == 0 {
+                    continue;
+                }
+                writeln!(output, "{}{}{}", spectrum.label(bin), separator, count)?;
+            }
+        }
+        None => {
+            let histogram: Vec<Value> = displayed
+                .map(|((bin, count), (frequency, _))| {
+                    let frequency = match spectrum.bin_width() {
+                        Some(_) => json!(frequency),
+                        None => json!(bin),
+                    };
+                    json!({ "frequency": frequency, "count": count })
+                })
+                .collect();
+
+            let mut object = metadata;
+            object.insert(
+                "distinct_kmers".to_string(),
+                json!(spectrum.distinct_kmers()),
+            );
+            object.insert("total_kmers".to_string(), json!(spectrum.total_kmers()));
+            object.insert("histogram".to_string(), Value::Array(histogram));
+            serde_json::to_writer_pretty(&mut *output, &object)?;
+            writeln!(output)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the estimates of a fitted model, then its observed and fitted spectrum
+///
+/// In the TSV and CSV formats, the estimates are written as `# name: value` comment lines
+/// before the spectrum; the `histo` format is rejected.
+pub fn write_model<W: Write>(
+    output: &mut W,
+    fit: &ModelFit,
+    format: OutputFormat,
+    metadata: Map<String, Value>,
+) -> io::Result<()> {
+    format.reject_histo("model fits")?;
+    let estimates = json!({
+        "genome_size": fit.genome_size.round() as u64,
+        "heterozygosity": fit.heterozygosity,
+        "error_rate": fit.error_rate,
+        "repeat_content": fit.repeat_content,
+        "haploid_coverage": fit.haploid_coverage,
+        "duplication": fit.duplication,
+        "overdispersion": fit.overdispersion,
+        "min_frequency": fit.min_frequency,
+    });
+    let estimates = match estimates {
+        Value::Object(map) => map,
+        _ => unreachable!(),
+    };
+
+    match format.separator() {
+        Some(separator) => {
+            for (name, value) in &estimates {
+                writeln!(output, "# {}: {}", name, value)?;
+            }
+            writeln!(output, "K-mer Frequency{0}Observed{0}Fitted", separator)?;
+            for point in &fit.curve {
+                writeln!(
+                    output,
+                    "{1}{0}{2}{0}{3:.2}",
+                    separator, point.frequency, point.observed, point.fitted
+                )?;
+            }
+        }
+        None => {
+            let curve: Vec<Value> = fit
+                .curve
+                .iter()
+                .map(|point| json!({ "frequency": point.frequency, "observed": point.observed, "fitted": point.fitted }))
+                .collect();
+
+            let mut object = metadata;
+            object.extend(estimates);
+            object.insert("curve".to_string(), Value::Array(curve));
+            serde_json::to_writer_pretty(&mut *output, &object)?;
+            writeln!(output)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the distances between two spectra, then the number of k-mers of each bin in both
+///
+/// As for the model, the distances are written as `# name: value` comment lines in the TSV and
+/// CSV formats; the `histo` format is rejected.
+pub fn write_comparison<W: Write>(
+    output: &mut W,
+    comparison: &Comparison,
+    format: OutputFormat,
+    metadata: Map<String, Value>,
+) -> io::Result<()> {
+    format.reject_histo("comparisons")?;
+    let distances = json!({
+        "first_distinct_kmers": comparison.first_kmers,
+        "second_distinct_kmers": comparison.second_kmers,
+        "l1_distance": comparison.l1_distance,
+        "kl_divergence": comparison.kl_divergence,
+        "jensen_shannon": comparison.jensen_shannon,
+    });
+    let distances = match distances {
+        Value::Object(map) => map,
+        _ => unreachable!(),
+    };
+
+    match format.separator() {
+        Some(separator) => {
+            for (name, value) in &distances {
+                writeln!(output, "# {}: {}", name, value)?;
+            }
+            writeln!(
+                output,
+                "K-mer Frequency{0}First{0}Second{0}Difference",
+                separator
+            )?;
+            for bin in &comparison.bins {
+                writeln!(
+                    output,
+                    "{1}{0}{2}{0}{3}{0}{4}",
+                    separator,
+                    comparison.label(bin.bin),
+                    bin.first,
+                    bin.second,
+                    bin.difference()
+                )?;
+            }
+        }
+        None => {
+            let histogram: Vec<Value> = comparison
+                .bins
+                .iter()
+                .map(|bin| {
+                    json!({
+                        "frequency": comparison.frequency(bin.bin),
+                        "first": bin.first,
+                        "second": bin.second,
+                        "difference": bin.difference(),
+                    })
+                })
+                .collect();
+
+            let mut object = metadata;
+            object.extend(distances);
+            object.insert("histogram".to_string(), Value::Array(histogram));
+            serde_json::to_writer_pretty(&mut *output, &object)?;
+            writeln!(output)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the valley, peaks and solid k-mer thresholds of named spectra, one row per spectrum
+///
+/// Missing values are written as `NA`; the frequencies of the peaks are separated by `,`
+/// (`;` in the CSV format). The `histo` format is rejected.
+pub fn write_peaks<W: Write>(
+    output: &mut W,
+    spectra: &[(String, Peaks)],
+    format: OutputFormat,
+    metadata: Map<String, Value>,
+) -> io::Result<()> {
+    format.reject_histo("peaks")?;
+    match format.separator() {
+        Some(separator) => {
+            let list_separator = if format == OutputFormat::Csv {
+                ";"
+            } else {
+                ","
+            };
+            writeln!(
+                output,
+                "Input{0}Valley{0}Main Peak{0}Peaks{0}Solid Min{0}Solid Max",
+                separator
+            )?;
+            for (name, peaks) in spectra {
+                let label =
+                    |bin: Option<u64>| bin.map_or_else(|| "NA".to_string(), |bin| peaks.label(bin));
+                let all_peaks: Vec<String> = peaks
+                    .peaks
+                    .iter()
+                    .map(|peak| peaks.label(peak.bin))
+                    .collect();
+                let all_peaks = if all_peaks.is_empty() {
+                    "NA".to_string()
+                } else {
+                    all_peaks.join(list_separator)
+                };
+                writeln!(
+                    output,
+                    "{1}{0}{2}{0}{3}{0}{4}{0}{5}{0}{6}",
+                    separator,
+                    name,
+                    label(peaks.valley.map(|valley| valley.bin)),
+                    label(peaks.main_peak().map(|peak| peak.bin)),
+                    all_peaks,
+                    label(peaks.solid_min),
+                    label(peaks.solid_max),
+                )?;
+            }
+        }
+        None => {
+            let spectra: Vec<Value> = spectra
+                .iter()
+                .map(|(name, peaks)| {
+                    let extremum = |extremum: &Extremum| {
+                        json!({
+                            "frequency": peaks.frequency(extremum.bin),
+                            "count": extremum.kmers,
+                            "smoothed": extremum.smoothed,
+                            "prominence": extremum.prominence,
+                        })
+                    };
+                    json!({
+                        "input": name,
+                        "valley": peaks.valley.as_ref().map(extremum),
+                        "main_peak": peaks.main_peak().map(|peak| peaks.frequency(peak.bin)),
+                        "peaks": peaks.peaks.iter().map(extremum).collect::<Vec<_>>(),
+                        "solid_min": peaks.solid_min.map(|bin| peaks.frequency(bin)),
+                        "solid_max": peaks.solid_max.map(|bin| peaks.frequency(bin)),
+                    })
+                })
+                .collect();
+
+            let mut object = metadata;
+            object.insert("spectra".to_string(), Value::Array(spectra));
+            serde_json::to_writer_pretty(&mut *output, &object)?;
+            writeln!(output)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the profile of the k-mer counts of each record, one row per record
+///
+/// Missing values (for records without k-mers) are written as `NA`. The solid k-mer threshold
+/// is only written in the JSON format, along with the run metadata. The `histo` format is
+/// rejected.
+pub fn write_profiles<W: Write>(
+    output: &mut W,
+    profiles: &Profiles,
+    format: OutputFormat,
+    metadata: Map<String, Value>,
+) -> io::Result<()> {
+    format.reject_histo("profiles")?;
+    match format.separator() {
+        Some(separator) => {
+            writeln!(
+                output,
+                "Record{0}Length{0}K-mers{0}Min{0}Median{0}Max{0}Solid Fraction",
+                separator
+            )?;
+            let value = |value: Option<f64>| {
+                value.map_or_else(|| "NA".to_string(), |value| value.to_string())
+            };
+            for record in &profiles.records {
+                writeln!(
+                    output,
+                    "{1}{0}{2}{0}{3}{0}{4}{0}{5}{0}{6}{0}{7}",
+                    separator,
+                    record.id,
+                    record.length,
+                    record.kmers,
+                    value(record.min),
+                    value(record.median),
+                    value(record.max),
+                    value(record.solid_fraction),
+                )?;
+            }
+        }
+        None => {
+            let records: Vec<Value> = profiles
+                .records
+                .iter()
+                .map(|record| {
+                    json!({
+                        "id": record.id,
+                        "length": record.length,
+                        "kmers": record.kmers,
+                        "min": record.min,
+                        "median": record.median,
+                        "max": record.max,
+                        "solid_fraction": record.solid_fraction,
+                    })
+                })
+                .collect();
+
+            let mut object = metadata;
+            object.insert("solid".to_string(), json!(profiles.solid));
+            object.insert("records".to_string(), Value::Array(records));
+            serde_json::to_writer_pretty(&mut *output, &object)?;
+            writeln!(output)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the run statistics of named spectra as a JSON object, along with the run metadata
+pub fn write_stats<W: Write>(
+    output: &mut W,
+    spectra: &[(String, RunStats)],
+    metadata: Map<String, Value>,
+) -> io::Result<()> {
+    let spectra: Vec<Value> = spectra
+        .iter()
+        .map(|(name, stats)| {
+            json!({
+                "input": name,
+                "records": stats.records,
+                "skipped_records": {
+                    "missing_abundance": stats.missing_abundance,
+                    "malformed_abundance": stats.malformed_abundance,
+                    "abundance_out_of_bounds": stats.filtered_abundance,
+                    "length_out_of_bounds": stats.filtered_length,
+                    "header_filter": stats.filtered_header,
+                },
+                "bases": stats.bases,
+                "kmers": stats.kmers,
+                "invalid_kmers": stats.invalid_kmers,
+                "distinct_kmers": stats.distinct_kmers,
+                "weighted_kmers": stats.weighted_kmers,
+                "graph": stats.graph.as_ref().map(|graph| json!({
+                    "unitigs": graph.unitigs,
+                    "links": graph.links,
+                    "isolated": graph.isolated,
+                    "tips": graph.tips,
+                    "bubbles": graph.bubbles,
+                    "degrees": graph.degrees,
+                })),
+            })
+        })
+        .collect();
+
+    let mut object = metadata;
+    object.insert("spectra".to_string(), Value::Array(spectra));
+    serde_json::to_writer_pretty(&mut *output, &object)?;
+    writeln!(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::RecordProfile;
+
+    /// A small spectrum, with a bin 0 from a fractional abundance
+    fn spectrum(bin_width: Option<f64>) -> Spectrum {
+        let width = bin_width.unwrap_or(1.0);
+        let mut spectrum = Spectrum::new(bin_width);
+        for (bin, kmers) in [(0, 2), (1, 5), (3, 1), (4, 2)] {
+            spectrum.add(bin as f64 * width, kmers);
+        }
+        spectrum
+    }
+
+    /// Writes the small spectrum in `format`
+    fn written(format: OutputFormat, bin_width: Option<f64>, limit: Option<u64>) -> String {
+        let mut metadata = Map::new();
+        metadata.insert("k".to_string(), json!(21));
+        let mut output = Vec::new();
+        write_histogram(&mut output, &spectrum(bin_width), format, limit, metadata).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn writes_delimited_formats() {
+        assert_eq!(
+            written(OutputFormat::Tsv, None, None),
+            "K-mer Frequency\tCount\n0\t2\n1\t5\n3\t1\n4\t2\n"
+        );
+        assert_eq!(
+            written(OutputFormat::Csv, None, Some(3)),
+            "K-mer Frequency,Count\n0,2\n1,5\n3,1\n"
+        );
+        assert_eq!(
+            written(OutputFormat::Tsv, Some(0.5), Some(1)),
+            "K-mer Frequency\tCount\n0.0\t2\n0.5\t5\n"
+        );
+    }
+
+    #[test]
+    fn writes_histo_from_frequency_one() {
+        assert_eq!(written(OutputFormat::Histo, None, None), "1 5\n3 1\n4 2\n");
+        assert_eq!(
+            written(OutputFormat::Histo, Some(1.0), Some(3)),
+            "1 5\n3 1\n"
+        );
+    }
+
+    #[test]
+    fn rejects_histo_with_fractional_bins() {
+        let error = write_histogram(
+            &mut Vec::new(),
+            &spectrum(Some(0.5)),
+            OutputFormat::Histo,
+            None,
+            Map::new(),
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn writes_json_with_metadata_and_totals() {
+        let value: Value =
+            serde_json::from_str(&written(OutputFormat::Json, None, Some(1))).unwrap();
+        assert_eq!(value["k"], 21);
+        assert_eq!(value["distinct_kmers"], 10);
+        assert_eq!(value["total_kmers"], 16.0);
+        assert_eq!(
+            value["histogram"],
+            json!([{ "frequency": 0, "count": 2 }, { "frequency": 1, "count": 5 }])
+        );
+
+        let value: Value =
+            serde_json::from_str(&written(OutputFormat::Json, Some(0.5), None)).unwrap();
+        assert_eq!(value["total_kmers"], 8.0);
+        assert_eq!(
+            value["histogram"][3],
+            json!({ "frequency": 2.0, "count": 2 })
+        );
+    }
+
+    #[test]
+    fn writes_profiles_with_missing_values() {
+        let profiles = Profiles {
+            spectrum: spectrum(None),
+            solid: Some(2.0),
+            records: vec![
+                RecordProfile::new("a", 4, &mut [1.0, 3.0], Some(2.0)),
+                RecordProfile::new("b", 2, &mut [], Some(2.0)),
+            ],
+        };
+        let written = |format| {
+            let mut output = Vec::new();
+            write_profiles(&mut output, &profiles, format, Map::new()).map(|()| output)
+        };
+        assert_eq!(
+            String::from_utf8(written(OutputFormat::Csv).unwrap()).unwrap(),
+            "Record,Length,K-mers,Min,Median,Max,Solid Fraction\n\
+             a,4,2,1,2,3,0.5\n\
+             b,2,0,NA,NA,NA,NA\n"
+        );
+        let value: Value = serde_json::from_slice(&written(OutputFormat::Json).unwrap()).unwrap();
+        assert_eq!(value["solid"], 2.0);
+        assert_eq!(value["records"][1]["median"], Value::Null);
+        let error = written(OutputFormat::Histo).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
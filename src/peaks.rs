@@ -0,0 +1,239 @@
+//! Detection of the error valley and of the coverage peaks of a k-mer spectrum
+//!
+//! The number of k-mers of each bin is optionally smoothed first. Error k-mers form a
+//! decreasing curve ending at the first local minimum, the valley; local maxima above it are
+//! coverage peaks when they stand out enough from the valleys separating them from higher ones.
+
+use clap::ValueEnum;
+
+use crate::spectrum::{bin_label, bin_lower_bound, Spectrum};
+
+/// Smoothing applied to the spectrum before looking for its extrema
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Smoothing {
+    /// Raw number of k-mers of each bin
+    None,
+    /// Moving average over the window
+    Average,
+    /// Moving median over the window, robust to isolated spikes
+    Median,
+}
+
+/// A local extremum of the spectrum
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Extremum {
+    /// Histogram bin of the extremum
+    pub bin: u64,
+    /// Number of k-mers of the bin
+    pub kmers: u64,
+    /// Smoothed number of k-mers of the bin
+    pub smoothed: f64,
+    /// Smoothed height of a peak above the highest valley separating it from a higher peak
+    /// or from the end of the spectrum (0 for the valley)
+    pub prominence: f64,
+}
+
+/// Error valley, coverage peaks and suggested solid k-mer thresholds of a spectrum
+#[derive(Clone, Debug, PartialEq)]
+pub struct Peaks {
+    /// First local minimum, separating error k-mers from solid ones, or `None` if the
+    /// spectrum does not start by decreasing
+    pub valley: Option<Extremum>,
+    /// Coverage peaks above the valley, by increasing frequency
+    pub peaks: Vec<Extremum>,
+    /// Lowest bin of solid k-mers: the valley, or the first bin without valley
+    pub solid_min: Option<u64>,
+    /// First bin past the last peak whose smoothed number of k-mers falls back under the one
+    /// of `solid_min`: k-mers from this bin on are likely repeats
+    pub solid_max: Option<u64>,
+    /// Width of the bins of the spectrum
+    pub bin_width: Option<f64>,
+}
+
+impl Peaks {
+    /// Highest peak of the smoothed spectrum
+    pub fn main_peak(&self) -> Option<&Extremum> {
+        self.peaks.iter().reduce(|best, peak| {
+            if peak.smoothed > best.smoothed {
+                peak
+            } else {
+                best
+            }
+        })
+    }
+
+    /// Lower bound of the k-mer counts of a bin
+    pub fn frequency(&self, bin: u64) -> f64 {
+        bin_lower_bound(bin, self.bin_width)
+    }
+
+    /// Formats the lower bound of the k-mer counts of a bin
+    pub fn label(&self, bin: u64) -> String {
+        bin_label(bin, self.bin_width)
+    }
+}
+
+/// Finds the valley, the peaks and the solid k-mer thresholds of a spectrum smoothed over
+/// `window` bins
+///
+/// Peaks whose prominence is under `min_prominence` times the height of the highest one
+/// are ignored.
+pub fn find_peaks(
+    spectrum: &Spectrum,
+    smoothing: Smoothing,
+    window: usize,
+    min_prominence: f64,
+) -> Peaks {
+    let bins = spectrum.dense_bins(None);
+    let counts: Vec<f64> = bins.iter().map(|&(_, kmers)| kmers as f64).collect();
+    let smoothed = smooth(&counts, smoothing, window);
+    let extremum = |i: usize, prominence: f64| Extremum {
+        bin: bins[i].0,
+        kmers: bins[i].1,
+        smoothed: smoothed[i],
+        prominence,
+    };
+
+    let valley = first_minimum(&smoothed);
+    let start = valley.unwrap_or(0);
+    // Plateaus are considered at their first bin; those followed by a rise have no prominence
+    let maxima: Vec<(usize, f64)> = (start..smoothed.len())
+        .filter(|&i| {
+            (i == start || smoothed[i] > smoothed[i - 1])
+                && (i + 1 == smoothed.len() || smoothed[i] >= smoothed[i + 1])
+        })
+        .map(|i| (i, prominence(&smoothed[start..], i - start)))
+        .collect();
+    let highest = maxima.iter().map(|&(i, _)| smoothed[i]).fold(0.0, f64::max);
+    let peaks: Vec<Extremum> = maxima
+        .into_iter()
+        .filter(|&(_, prominence)| prominence > 0.0 && prominence >= min_prominence * highest)
+        .map(|(i, prominence)| extremum(i, prominence))
+        .collect();
+
+    let solid_min = (start < bins.len()).then_some(start);
+    let solid_max = solid_min.zip(peaks.last()).and_then(|(min, last)| {
+        let last = (last.bin - bins[0].0) as usize;
+        (last + 1..smoothed.len()).find(|&i| smoothed[i] < smoothed[min])
+    });
+
+    Peaks {
+        valley: valley.map(|i| extremum(i, 0.0)),
+        peaks,
+        solid_min: solid_min.map(|i| bins[i].0),
+        solid_max: solid_max.map(|i| bins[i].0),
+        bin_width: spectrum.bin_width(),
+    }
+}
+
+/// Index of the first local minimum of a curve: the last point before it first rises,
+/// or `None` if it rises right away or never does
+pub fn first_minimum(counts: &[f64]) -> Option<usize> {
+    (0..counts.len().saturating_sub(1))
+        .find(|&i| counts[i + 1] > counts[i])
+        .filter(|&i| i > 0)
+}
+
+/// Smooths counts over a centred window of `window` points, truncated at both ends
+fn smooth(counts: &[f64], smoothing: Smoothing, window: usize) -> Vec<f64> {
+    let half = window / 2;
+    (0..counts.len())
+        .map(|i| {
+            let neighbours = &counts[i.saturating_sub(half)..(i + half + 1).min(counts.len())];
+            match smoothing {
+                Smoothing::None => counts[i],
+                Smoothing::Average => neighbours.iter().sum::<f64>() / neighbours.len() as f64,
+                Smoothing::Median => {
+                    let mut sorted = neighbours.to_vec();
+                    sorted.sort_by(f64::total_cmp);
+                    let middle = sorted.len() / 2;
+                    if sorted.len() % 2 == 1 {
+                        sorted[middle]
+                    } else {
+                        (sorted[middle - 1] + sorted[middle]) / 2.0
+                    }
+                }
+            }
+        })
+        .collect()
+}
+
+/// Height of the maximum at `i` above the higher of the lowest points separating it, on each
+/// side, from a higher point or from the end of the curve
+fn prominence(curve: &[f64], i: usize) -> f64 {
+    let base = |side: &mut dyn Iterator<Item = &f64>| {
+        side.take_while(|&&value| value <= curve[i])
+            .fold(curve[i], |base, &value| base.min(value))
+    };
+    let left = base(&mut curve[..i].iter().rev());
+    let right = base(&mut curve[i + 1..].iter());
+    curve[i] - left.max(right)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Spectrum with the given number of k-mers for the counts from 1
+    fn spectrum(kmers: &[u64]) -> Spectrum {
+        let mut spectrum = Spectrum::new(None);
+        for (count, &kmers) in (1..).zip(kmers) {
+            spectrum.add(count as f64, kmers);
+        }
+        spectrum
+    }
+
+    #[test]
+    fn finds_the_valley_and_the_peaks_of_a_bimodal_spectrum() {
+        // Errors down to a valley at 4, a heterozygous peak at 7 and a homozygous one at 12
+        let bimodal = spectrum(&[100, 40, 10, 5, 8, 20, 30, 20, 10, 15, 40, 60, 40, 15, 4, 2]);
+        let peaks = find_peaks(&bimodal, Smoothing::None, 1, 0.05);
+        let valley = peaks.valley.unwrap();
+        assert_eq!((valley.bin, valley.kmers), (4, 5));
+        let found: Vec<(u64, f64)> = peaks
+            .peaks
+            .iter()
+            .map(|peak| (peak.bin, peak.prominence))
+            .collect();
+        assert_eq!(found, [(7, 20.0), (12, 55.0)]);
+        assert_eq!(peaks.main_peak().unwrap().bin, 12);
+        assert_eq!(peaks.solid_min, Some(4));
+        assert_eq!(peaks.solid_max, Some(15));
+
+        // The heterozygous peak does not stand out enough from the valley before the next one
+        let peaks = find_peaks(&bimodal, Smoothing::None, 1, 0.5);
+        assert_eq!(peaks.peaks.len(), 1);
+        assert_eq!(peaks.main_peak().unwrap().bin, 12);
+
+        // The moving median flattens the valley and the main peak into plateaus, over 4 and 5
+        // and over 11 to 13
+        for (smoothing, valley, main_peak) in
+            [(Smoothing::Average, 4, 12), (Smoothing::Median, 5, 11)]
+        {
+            let peaks = find_peaks(&bimodal, smoothing, 3, 0.05);
+            assert_eq!(peaks.valley.unwrap().bin, valley, "{:?}", smoothing);
+            assert_eq!(peaks.main_peak().unwrap().bin, main_peak, "{:?}", smoothing);
+        }
+    }
+
+    #[test]
+    fn flat_and_empty_spectra_have_no_peak() {
+        let peaks = find_peaks(&spectrum(&[50; 10]), Smoothing::Average, 3, 0.05);
+        assert_eq!(peaks.valley, None);
+        assert!(peaks.peaks.is_empty());
+        assert_eq!(peaks.main_peak(), None);
+        assert_eq!((peaks.solid_min, peaks.solid_max), (Some(1), None));
+
+        let peaks = find_peaks(&Spectrum::new(None), Smoothing::Average, 3, 0.05);
+        assert_eq!(peaks.valley, None);
+        assert!(peaks.peaks.is_empty());
+        assert_eq!((peaks.solid_min, peaks.solid_max), (None, None));
+    }
+
+    #[test]
+    fn median_of_an_even_window_averages_its_middle_values() {
+        let smoothed = smooth(&[1.0, 9.0, 3.0, 4.0], Smoothing::Median, 2);
+        // Windows truncated at both ends hold 2 points, the other ones 3
+        assert_eq!(smoothed, [5.0, 3.0, 4.0, 3.5]);
+    }
+}
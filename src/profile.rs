@@ -0,0 +1,109 @@
+//! Profiles of the global counts of the k-mers of each record
+//!
+//! The k-mers of a genuine contig have counts close to its coverage. A contig whose k-mers have
+//! much lower counts than the rest of the assembly, or a wide range of counts, is more likely to
+//! be a contaminant or a chimera joining sequences of different coverages.
+
+use crate::spectrum::Spectrum;
+
+/// Distribution of the global counts of the k-mers of a record
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordProfile {
+    /// Identifier of the record
+    pub id: String,
+    /// Length of the record
+    pub length: usize,
+    /// Number of k-mers of the record
+    pub kmers: usize,
+    /// Lowest count of the k-mers of the record, or `None` if it has none
+    pub min: Option<f64>,
+    /// Median count of the k-mers of the record
+    pub median: Option<f64>,
+    /// Highest count of the k-mers of the record
+    pub max: Option<f64>,
+    /// Fraction of the k-mers of the record that are solid, or `None` without solid threshold
+    pub solid_fraction: Option<f64>,
+}
+
+impl RecordProfile {
+    /// Profile of a record from the global counts of its k-mers, in any order (0 for the k-mers
+    /// that were not counted, e.g. in skipped records); k-mers are solid from the `solid` count
+    pub fn new(id: &str, length: usize, counts: &mut [f64], solid: Option<f64>) -> Self {
+        counts.sort_unstable_by(f64::total_cmp);
+        let middle = counts.len() / 2;
+        let median = match counts.len() {
+            0 => None,
+            n if n % 2 == 1 => Some(counts[middle]),
+            _ => Some((counts[middle - 1] + counts[middle]) / 2.0),
+        };
+        let solid_fraction = solid.filter(|_| !counts.is_empty()).map(|solid| {
+            let solid_kmers = counts.iter().filter(|&&count| count >= solid).count();
+            solid_kmers as f64 / counts.len() as f64
+        });
+        RecordProfile {
+            id: id.to_string(),
+            length,
+            kmers: counts.len(),
+            min: counts.first().copied(),
+            median,
+            max: counts.last().copied(),
+            solid_fraction,
+        }
+    }
+}
+
+/// Spectrum of the k-mers of all records, and profile of each record
+#[derive(Clone, Debug)]
+pub struct Profiles {
+    /// Spectrum of the k-mers of all records
+    pub spectrum: Spectrum,
+    /// Lowest count of solid k-mers
+    pub solid: Option<f64>,
+    /// Profile of each record, in input order
+    pub records: Vec<RecordProfile>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_unsorted_counts() {
+        let profile = RecordProfile::new("a", 7, &mut [4.0, 1.0, 9.0, 3.0, 5.0], Some(4.0));
+        assert_eq!(
+            profile,
+            RecordProfile {
+                id: "a".to_string(),
+                length: 7,
+                kmers: 5,
+                min: Some(1.0),
+                median: Some(4.0),
+                max: Some(9.0),
+                solid_fraction: Some(0.6),
+            }
+        );
+    }
+
+    #[test]
+    fn averages_the_middle_counts_of_even_records() {
+        let profile = RecordProfile::new("a", 6, &mut [8.0, 2.0, 0.0, 3.0], None);
+        assert_eq!(profile.median, Some(2.5));
+        assert_eq!((profile.min, profile.max), (Some(0.0), Some(8.0)));
+        assert_eq!(profile.solid_fraction, None);
+    }
+
+    #[test]
+    fn leaves_records_without_kmers_unprofiled() {
+        let profile = RecordProfile::new("a", 2, &mut [], Some(1.0));
+        assert_eq!(profile.kmers, 0);
+        assert_eq!(
+            (
+                profile.min,
+                profile.median,
+                profile.max,
+                profile.solid_fraction
+            ),
+            (None, None, None, None)
+        );
+    }
+}
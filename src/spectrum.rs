@@ -0,0 +1,1337 @@
+//! k-mer spectra and their construction from sequence files
+
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use crate::abundance::{AbundanceSource, Aggregation, HeaderAbundance, MissingAbundance};
+use crate::count::{
+    count_kmers, count_kmers_by_group, count_kmers_parallel, spectrum_from_lengths,
+    spectrum_from_lengths_by_group, KmerCounts,
+};
+use crate::disk::count_kmers_on_disk;
+use crate::dump::{write_dump, DumpFormat};
+use crate::graph::{node_id, parse_links, GraphStats, LinkGraph};
+use crate::header::{HeaderFilter, HeaderGroup, HeaderGroups, LoganHeader};
+use crate::input::{read_inputs, read_records, with_path, Format, OpenedInput, Record, STDIN_PATH};
+use crate::kmer::{generate_encoded_kmers, Kmer, MultiWord};
+use crate::peaks::{find_peaks, Smoothing};
+use crate::profile::{Profiles, RecordProfile};
+use crate::stats::{RunStats, SkipReason};
+
+/// Largest supported k-mer size
+pub const MAX_K: usize = MultiWord::<8>::MAX_K;
+
+/// Histogram bin of a (possibly fractional) k-mer count
+///
+/// Without a bin width, counts are rounded to the nearest integer.
+pub fn histogram_bin(count: f64, bin_width: Option<f64>) -> u64 {
+    match bin_width {
+        Some(width) => (count / width).floor() as u64,
+        None => count.round() as u64,
+    }
+}
+
+/// Lower bound of the k-mer counts falling into a histogram bin
+pub fn bin_lower_bound(bin: u64, bin_width: Option<f64>) -> f64 {
+    bin as f64 * bin_width.unwrap_or(1.0)
+}
+
+/// Formats the lower bound of a histogram bin, with as many decimals as the bin width needs
+pub fn bin_label(bin: u64, bin_width: Option<f64>) -> String {
+    match bin_width {
+        Some(width) => format!(
+            "{:.*}",
+            width_decimals(width),
+            bin_lower_bound(bin, bin_width)
+        ),
+        None => bin.to_string(),
+    }
+}
+
+/// Number of decimals needed to write the multiples of a bin width
+pub(crate) fn width_decimals(width: f64) -> usize {
+    (0..9)
+        .find(|&d| (width * 10f64.powi(d)).fract().abs() < 1e-9)
+        .unwrap_or(9) as usize
+}
+
+/// A k-mer spectrum: the number of distinct k-mers for each (binned) k-mer count
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Spectrum {
+    histogram: BTreeMap<u64, u64>,
+    bin_width: Option<f64>,
+}
+
+impl Spectrum {
+    /// An empty spectrum, binning counts by `bin_width` or rounding them to integers
+    pub fn new(bin_width: Option<f64>) -> Self {
+        Spectrum {
+            histogram: BTreeMap::new(),
+            bin_width,
+        }
+    }
+
+    /// Adds `kmers` distinct k-mers with the given count
+    pub fn add(&mut self, count: f64, kmers: u64) {
+        *self
+            .histogram
+            .entry(histogram_bin(count, self.bin_width))
+            .or_insert(0) += kmers;
+    }
+
+    /// Adds `kmers` distinct k-mers to a histogram bin
+    pub fn add_bin(&mut self, bin: u64, kmers: u64) {
+        *self.histogram.entry(bin).or_insert(0) += kmers;
+    }
+
+    /// Adds the k-mers of another spectrum with the same binning
+    pub fn merge(&mut self, other: &Spectrum) {
+        for (&bin, &kmers) in &other.histogram {
+            *self.histogram.entry(bin).or_insert(0) += kmers;
+        }
+    }
+
+    /// Width of the bins, or `None` if counts are rounded to integers
+    pub fn bin_width(&self) -> Option<f64> {
+        self.bin_width
+    }
+
+    /// Non-empty bins and their number of k-mers, by increasing count
+    pub fn bins(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.histogram.iter().map(|(&bin, &kmers)| (bin, kmers))
+    }
+
+    /// Lower bound of the counts of each non-empty bin and its number of k-mers, by increasing count
+    pub fn iter(&self) -> impl Iterator<Item = (f64, u64)> + '_ {
+        self.bins()
+            .map(|(bin, kmers)| (bin_lower_bound(bin, self.bin_width), kmers))
+    }
+
+    /// All bins from the first one up to the last non-empty one whose lower bound is at most
+    /// `max_frequency`, including empty bins, with their number of k-mers
+    ///
+    /// Without a bin width, the bin of k-mers whose count rounds to 0 is left out.
+    pub fn dense_bins(&self, max_frequency: Option<f64>) -> Vec<(u64, u64)> {
+        let first = if self.bin_width.is_some() { 0 } else { 1 };
+        let mut dense = Vec::new();
+        for (bin, kmers) in self.bins().skip_while(|&(bin, _)| bin < first) {
+            if max_frequency.is_some_and(|max| bin_lower_bound(bin, self.bin_width) > max) {
+                break;
+            }
+            let next = first + dense.len() as u64;
+            dense.extend((next..bin).map(|empty| (empty, 0)));
+            dense.push((bin, kmers));
+        }
+        dense
+    }
+
+    /// Formats the lower bound of the counts of a bin
+    pub fn label(&self, bin: u64) -> String {
+        bin_label(bin, self.bin_width)
+    }
+
+    /// Number of distinct k-mers whose count falls into the same bin as `count`
+    pub fn get(&self, count: f64) -> u64 {
+        self.histogram
+            .get(&histogram_bin(count, self.bin_width))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Number of non-empty bins
+    pub fn len(&self) -> usize {
+        self.histogram.len()
+    }
+
+    /// Whether the spectrum has no k-mer
+    pub fn is_empty(&self) -> bool {
+        self.histogram.is_empty()
+    }
+
+    /// Number of distinct k-mers
+    pub fn distinct_kmers(&self) -> u64 {
+        self.histogram.values().sum()
+    }
+
+    /// Total number of k-mers, each distinct k-mer counted as the lower bound of its bin
+    pub fn total_kmers(&self) -> f64 {
+        self.iter().map(|(count, kmers)| count * kmers as f64).sum()
+    }
+}
+
+/// When to skip the k-mer hash table and build the spectrum from sequence lengths
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum FastPath {
+    /// When k equals the assembly k and all inputs are FASTA
+    Auto,
+    /// Always, even if k-mers may be shared between sequences
+    Always,
+    /// Never: always count k-mers in a hash table
+    Never,
+}
+
+/// Configures and computes k-mer spectra
+///
+/// ```no_run
+/// use logan_kmer_spectrum::{AbundanceSource, HeaderField, Rounding, SpectrumBuilder};
+///
+/// let spectrum = SpectrumBuilder::new(31)
+///     .canonical(true)
+///     .abundance(AbundanceSource::Header(HeaderField::default(), Rounding::Round))
+///     .build_from_paths(&["SRR1.contigs.fa.zst"])?;
+/// for (count, kmers) in spectrum.iter() {
+///     println!("{}\t{}", count, kmers);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct SpectrumBuilder {
+    k: usize,
+    canonical: bool,
+    abundance: AbundanceSource,
+    missing_abundance: MissingAbundance,
+    strict: bool,
+    aggregation: Aggregation,
+    bin_width: Option<f64>,
+    threads: usize,
+    max_memory: Option<usize>,
+    tmp_dir: Option<PathBuf>,
+    assembly_k: usize,
+    fast_path: FastPath,
+    dump: Option<(PathBuf, DumpFormat)>,
+    abundance_range: RangeInclusive<f64>,
+    count_range: RangeInclusive<f64>,
+    length_range: RangeInclusive<usize>,
+    trim: usize,
+    header_filters: Vec<HeaderFilter>,
+    dedup_junctions: bool,
+    graph_stats: bool,
+}
+
+/// Range of values between optional bounds
+fn bounds(min: Option<f64>, max: Option<f64>) -> RangeInclusive<f64> {
+    min.unwrap_or(f64::NEG_INFINITY)..=max.unwrap_or(f64::INFINITY)
+}
+
+impl SpectrumBuilder {
+    /// A builder for spectra of k-mers of size `k`, with the default settings: non-canonical
+    /// k-mers weighted by the Logan header abundance (skipping records without it), single-threaded
+    /// and in memory, with the fast path enabled for the Logan assembly k (31)
+    pub fn new(k: usize) -> Self {
+        SpectrumBuilder {
+            k,
+            canonical: false,
+            abundance: AbundanceSource::default(),
+            missing_abundance: MissingAbundance::default(),
+            strict: false,
+            aggregation: Aggregation::default(),
+            bin_width: None,
+            threads: 1,
+            max_memory: None,
+            tmp_dir: None,
+            assembly_k: 31,
+            fast_path: FastPath::Auto,
+            dump: None,
+            abundance_range: bounds(None, None),
+            count_range: bounds(None, None),
+            length_range: 0..=usize::MAX,
+            trim: 0,
+            header_filters: Vec::new(),
+            dedup_junctions: false,
+            graph_stats: false,
+        }
+    }
+
+    /// Considers all k-mers as canonical
+    pub fn canonical(mut self, canonical: bool) -> Self {
+        self.canonical = canonical;
+        self
+    }
+
+    /// Sets where the abundance weighting the k-mers of each record comes from
+    pub fn abundance(mut self, abundance: AbundanceSource) -> Self {
+        self.abundance = abundance;
+        self
+    }
+
+    /// Sets what to do with FASTA records whose header has no abundance
+    pub fn missing_abundance(mut self, missing_abundance: MissingAbundance) -> Self {
+        self.missing_abundance = missing_abundance;
+        self
+    }
+
+    /// Stops with an error on the first FASTA record whose abundance field is not a number,
+    /// instead of handling it as a missing abundance
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Sets how the abundances of the occurrences of a k-mer are combined into its count
+    pub fn aggregation(mut self, aggregation: Aggregation) -> Self {
+        self.aggregation = aggregation;
+        self
+    }
+
+    /// Bins k-mer counts into buckets of this width instead of rounding them to integers
+    pub fn bin_width(mut self, bin_width: Option<f64>) -> Self {
+        self.bin_width = bin_width;
+        self
+    }
+
+    /// Extracts and counts k-mers with this number of worker threads
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Counts k-mers through temporary bucket files, keeping each in-memory table under this size
+    pub fn max_memory(mut self, max_memory: Option<usize>) -> Self {
+        self.max_memory = max_memory;
+        self
+    }
+
+    /// Directory of the temporary bucket files (default: system temporary directory)
+    pub fn tmp_dir(mut self, tmp_dir: Option<PathBuf>) -> Self {
+        self.tmp_dir = tmp_dir;
+        self
+    }
+
+    /// k used to build the input unitigs or contigs, which enables the fast path
+    pub fn assembly_k(mut self, assembly_k: usize) -> Self {
+        self.assembly_k = assembly_k;
+        self
+    }
+
+    /// Sets when to build the spectrum from sequence lengths only
+    pub fn fast_path(mut self, fast_path: FastPath) -> Self {
+        self.fast_path = fast_path;
+        self
+    }
+
+    /// Writes every k-mer and its count into a file, sorted by k-mer, when computing the spectrum
+    ///
+    /// The k-mers are then counted in memory, without the fast path.
+    pub fn dump(mut self, dump: Option<(PathBuf, DumpFormat)>) -> Self {
+        self.dump = dump;
+        self
+    }
+
+    /// Skips the records whose abundance (after rounding, or the one given to records without
+    /// abundance) is out of the given bounds
+    pub fn abundance_range(mut self, min: Option<f64>, max: Option<f64>) -> Self {
+        self.abundance_range = bounds(min, max);
+        self
+    }
+
+    /// Drops the k-mers whose weighted count is out of the given bounds, from the spectrum as
+    /// well as from the counts and dumps
+    pub fn count_range(mut self, min: Option<f64>, max: Option<f64>) -> Self {
+        self.count_range = bounds(min, max);
+        self
+    }
+
+    /// Skips the records whose sequence length is out of the given bounds
+    pub fn length_range(mut self, min: Option<usize>, max: Option<usize>) -> Self {
+        self.length_range = min.unwrap_or(0)..=max.unwrap_or(usize::MAX);
+        self
+    }
+
+    /// Ignores the first and last `trim` bases of each sequence, such as the k-1 bases of
+    /// overlap between neighbouring unitigs
+    pub fn trim(mut self, trim: usize) -> Self {
+        self.trim = trim;
+        self
+    }
+
+    /// Skips the records whose Logan header does not satisfy all the given conditions
+    pub fn header_filters(mut self, header_filters: Vec<HeaderFilter>) -> Self {
+        self.header_filters = header_filters;
+        self
+    }
+
+    /// Counts the k-mers of each junction between unitigs linked in their headers (`L:+:12:-`)
+    /// once, in the unitig of smallest identifier meeting at it, instead of once per unitig
+    ///
+    /// Linked unitigs overlap by the assembly k minus 1 bases, so this only matters for k below
+    /// the assembly k. Since linked unitigs may be read in opposite orientations, k-mers must
+    /// be canonical. The headers of each input file are read in a first pass, so the inputs cannot
+    /// be records or the standard input.
+    pub fn dedup_junctions(mut self, dedup_junctions: bool) -> Self {
+        self.dedup_junctions = dedup_junctions;
+        self
+    }
+
+    /// Reads the graph of the unitigs linked in their headers to report its statistics along
+    /// with the run statistics, in a first pass over the input files
+    pub fn graph_stats(mut self, graph_stats: bool) -> Self {
+        self.graph_stats = graph_stats;
+        self
+    }
+
+    /// Size of the k-mers
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// Whether k-mers are considered as canonical
+    pub fn is_canonical(&self) -> bool {
+        self.canonical
+    }
+
+    /// Checks that the settings are consistent
+    pub fn validate(&self) -> io::Result<()> {
+        let invalid = |message: String| Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        if self.k == 0 || self.k > MAX_K {
+            return invalid(format!("k-mer size must be between 1 and {}", MAX_K));
+        }
+        if self
+            .bin_width
+            .is_some_and(|width| !width.is_finite() || width <= 0.0)
+        {
+            return invalid("bin width must be strictly positive".to_string());
+        }
+        if self.threads == 0 {
+            return invalid("number of threads must be at least 1".to_string());
+        }
+        if self
+            .max_memory
+            .is_some_and(|max_memory| max_memory < 1 << 20)
+        {
+            return invalid("maximum memory must be at least 1M".to_string());
+        }
+        if self.max_memory.is_some() && self.threads > 1 {
+            return invalid("disk-backed counting is single-threaded".to_string());
+        }
+        for (name, range) in [
+            ("abundance", &self.abundance_range),
+            ("count", &self.count_range),
+        ] {
+            if range.start().is_nan() || range.end().is_nan() || range.start() > range.end() {
+                return invalid(format!("minimum {} cannot exceed the maximum one", name));
+            }
+        }
+        if self.length_range.is_empty() {
+            return invalid("minimum length cannot exceed the maximum one".to_string());
+        }
+        if self.max_memory.is_some() && self.dump.is_some() {
+            return invalid("k-mer counts cannot be dumped with disk-backed counting".to_string());
+        }
+        if self.dedup_junctions && !self.canonical {
+            return invalid(
+                "junctions can only be deduplicated with canonical k-mers, since linked unitigs \
+                 may be read in opposite orientations"
+                    .to_string(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Computes the spectrum of the k-mers of all input files (`-` for the standard input)
+    pub fn build_from_paths<P: AsRef<Path>>(&self, inputs: &[P]) -> io::Result<Spectrum> {
+        self.build_paths(inputs, None)
+    }
+
+    /// Computes the spectrum of the k-mers of all input files, along with run statistics
+    pub fn build_from_paths_with_stats<P: AsRef<Path>>(
+        &self,
+        inputs: &[P],
+    ) -> io::Result<(Spectrum, RunStats)> {
+        let mut stats = RunStats::default();
+        let spectrum = self.build_paths(inputs, Some(&mut stats))?;
+        Ok((spectrum, stats))
+    }
+
+    /// Computes the spectrum of the k-mers of the given records
+    ///
+    /// With [`FastPath::Auto`], the fast path is taken when k equals the assembly k: the
+    /// records must then come from an assembly.
+    pub fn build_from_records<I>(&self, records: I) -> io::Result<Spectrum>
+    where
+        I: Iterator<Item = io::Result<Record>>,
+    {
+        self.build(records, true, None, None)
+    }
+
+    /// Computes the spectrum of the k-mers of the given records, along with run statistics
+    pub fn build_from_records_with_stats<I>(&self, records: I) -> io::Result<(Spectrum, RunStats)>
+    where
+        I: Iterator<Item = io::Result<Record>>,
+    {
+        let mut stats = RunStats::default();
+        let spectrum = self.build(records, true, Some(&mut stats), None)?;
+        Ok((spectrum, stats))
+    }
+
+    /// Computes the spectrum of the k-mers of all input files, updating `stats` if given
+    fn build_paths<P: AsRef<Path>>(
+        &self,
+        inputs: &[P],
+        mut stats: Option<&mut RunStats>,
+    ) -> io::Result<Spectrum> {
+        let inputs: Vec<PathBuf> = inputs
+            .iter()
+            .map(|input| input.as_ref().to_path_buf())
+            .collect();
+        let (all_fasta, stdin) = self.open_inputs(&inputs)?;
+        let junctions = if self.dedup_junctions || (self.graph_stats && stats.is_some()) {
+            let (owned, graph_stats) = self.read_links(&inputs)?;
+            if let Some(stats) = stats.as_deref_mut() {
+                stats.graph = Some(graph_stats);
+            }
+            self.dedup_junctions.then_some(owned)
+        } else {
+            None
+        };
+        self.build(read_inputs(&inputs, stdin), all_fasta, stats, junctions)
+    }
+
+    /// Checks whether all input files are FASTA files if the fast path may be taken, and returns
+    /// it along with the opened standard input if it is one of them, since it cannot be opened
+    /// again to read its records
+    fn open_inputs(&self, inputs: &[PathBuf]) -> io::Result<(bool, Option<OpenedInput>)> {
+        // Reads share their k-mers, unlike the unitigs or contigs of an assembly
+        let mut stdin = None;
+        let all_fasta = match self.fast_path {
+            FastPath::Auto if self.k == self.assembly_k => {
+                let mut all_fasta = true;
+                for input in inputs {
+                    let opened = OpenedInput::open(input).map_err(|e| with_path(e, input))?;
+                    all_fasta &= opened.format() == Format::Fasta;
+                    if input == Path::new(STDIN_PATH) {
+                        stdin = Some(opened);
+                    }
+                }
+                all_fasta
+            }
+            _ => true,
+        };
+        Ok((all_fasta, stdin))
+    }
+
+    /// Computes one spectrum per group of the records of all input files (`-` for the standard
+    /// input), grouped by a column of their Logan header, along with the run statistics of each
+    /// group, reading the inputs once
+    ///
+    /// Groups come in increasing order, and records without the column are skipped. All groups
+    /// are counted together in memory on the calling thread, without a dump nor graph
+    /// statistics.
+    pub fn build_groups_from_paths<P: AsRef<Path>>(
+        &self,
+        inputs: &[P],
+        groups: &HeaderGroups,
+    ) -> io::Result<Vec<(HeaderGroup, Spectrum, RunStats)>> {
+        self.validate()?;
+        if self.threads > 1 || self.max_memory.is_some() || self.dump.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "groups of records are counted in memory on a single thread, without a dump",
+            ));
+        }
+        let inputs: Vec<PathBuf> = inputs
+            .iter()
+            .map(|input| input.as_ref().to_path_buf())
+            .collect();
+        let (all_fasta, stdin) = self.open_inputs(&inputs)?;
+        let mut junctions = if self.dedup_junctions {
+            Some(self.read_links(&inputs)?.0.into_iter())
+        } else {
+            None
+        };
+
+        let mut stats = BTreeMap::new();
+        let records = read_inputs(&inputs, stdin).filter_map(|result| {
+            let owned = junctions
+                .as_mut()
+                .and_then(Iterator::next)
+                .unwrap_or((true, true));
+            let grouped = result.and_then(|record| {
+                let group = groups
+                    .group(&LoganHeader::from_record(&record))
+                    .map_err(|e| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("{} in the header of record `{}`", e, record.id()),
+                        )
+                    })?;
+                let Some(group) = group else {
+                    return Ok(None);
+                };
+                let stats = stats.entry(group.clone()).or_default();
+                let inspected = self.inspect(record, owned, Some(stats))?;
+                Ok(inspected.map(|record| (group, record)))
+            });
+            grouped.transpose()
+        });
+
+        let mut spectra = if self.takes_fast_path(all_fasta) {
+            let weight = |record: &Record| {
+                self.weight(record)
+                    .filter(|count| self.count_range.contains(count))
+            };
+            spectrum_from_lengths_by_group(records, self.k, self.trim, weight, self.bin_width)?
+        } else {
+            match self.k {
+                k if k <= u64::MAX_K => self.run_groups::<u64, _>(records)?,
+                k if k <= u128::MAX_K => self.run_groups::<u128, _>(records)?,
+                k if k <= MultiWord::<4>::MAX_K => self.run_groups::<MultiWord<4>, _>(records)?,
+                _ => self.run_groups::<MultiWord<8>, _>(records)?,
+            }
+        };
+
+        Ok(stats
+            .into_iter()
+            .map(|(group, mut stats): (HeaderGroup, RunStats)| {
+                let spectrum = spectra
+                    .remove(&group)
+                    .unwrap_or_else(|| Spectrum::new(self.bin_width));
+                stats.distinct_kmers = spectrum.distinct_kmers();
+                (group, spectrum, stats)
+            })
+            .collect())
+    }
+
+    /// Counts the k-mers of each group of records with the `K` representation and computes
+    /// their spectra
+    fn run_groups<K, I>(&self, records: I) -> io::Result<BTreeMap<HeaderGroup, Spectrum>>
+    where
+        K: Kmer,
+        I: Iterator<Item = io::Result<(HeaderGroup, Record)>>,
+    {
+        let weight = |record: &Record| self.weight(record);
+        let groups = count_kmers_by_group::<K, _, _, _>(
+            records,
+            self.k,
+            self.canonical,
+            self.trim,
+            weight,
+            self.aggregation,
+        )?;
+        Ok(groups
+            .into_iter()
+            .map(|(group, kmer_counts)| {
+                let mut spectrum = Spectrum::new(self.bin_width);
+                for count in kmer_counts.values() {
+                    if self.count_range.contains(&count) {
+                        spectrum.add(count, 1);
+                    }
+                }
+                (group, spectrum)
+            })
+            .collect())
+    }
+
+    /// Reads the links of the headers of each input file, and returns whether each record, in
+    /// input order, owns the junctions at its start and at its end, with the graph statistics
+    fn read_links(&self, inputs: &[PathBuf]) -> io::Result<(Vec<(bool, bool)>, GraphStats)> {
+        if inputs.iter().any(|input| input == Path::new(STDIN_PATH)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unitig links cannot be read from the standard input, which is only read once",
+            ));
+        }
+        let mut owned = Vec::new();
+        let mut graph_stats = GraphStats::default();
+        for input in inputs {
+            // Links refer to the unitigs of the same input
+            let mut graph = LinkGraph::new();
+            let mut nodes = Vec::new();
+            for record in read_records(std::slice::from_ref(input)) {
+                let record = record?;
+                let node = node_id(record.id());
+                if let Some(node) = node {
+                    graph.add(node, parse_links(record.desc().unwrap_or_default()));
+                }
+                nodes.push(node);
+            }
+            owned.extend(
+                nodes
+                    .into_iter()
+                    .map(|node| node.map_or((true, true), |node| graph.owns_junctions(node))),
+            );
+            graph_stats.merge(&graph.stats());
+        }
+        Ok((owned, graph_stats))
+    }
+
+    /// Counts the k-mers of all input files in memory, then reads them again to profile the
+    /// counts of the k-mers of each record
+    ///
+    /// k-mers are solid from the `solid` count, or by default from the error valley of the
+    /// spectrum smoothed over `window` bins, ignoring the peaks under `min_prominence` (see
+    /// [`find_peaks`]). Every record is profiled, including the skipped ones, and the k-mers of
+    /// the junctions are counted once if junctions are deduplicated.
+    pub fn profile_paths<P: AsRef<Path>>(
+        &self,
+        inputs: &[P],
+        solid: Option<f64>,
+        smoothing: Smoothing,
+        window: usize,
+        min_prominence: f64,
+    ) -> io::Result<Profiles> {
+        self.validate()?;
+        if self.max_memory.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "records are profiled from k-mers counted in memory, without a memory limit",
+            ));
+        }
+        let inputs: Vec<PathBuf> = inputs
+            .iter()
+            .map(|input| input.as_ref().to_path_buf())
+            .collect();
+        if inputs.iter().any(|input| input == Path::new(STDIN_PATH)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "records cannot be profiled from the standard input, which is only read once",
+            ));
+        }
+        let valley = |spectrum: &Spectrum| {
+            let peaks = find_peaks(spectrum, smoothing, window, min_prominence);
+            peaks.solid_min.map(|bin| peaks.frequency(bin))
+        };
+        match self.k {
+            k if k <= u64::MAX_K => self.profile::<u64, _>(&inputs, solid, valley),
+            k if k <= u128::MAX_K => self.profile::<u128, _>(&inputs, solid, valley),
+            k if k <= MultiWord::<4>::MAX_K => {
+                self.profile::<MultiWord<4>, _>(&inputs, solid, valley)
+            }
+            _ => self.profile::<MultiWord<8>, _>(&inputs, solid, valley),
+        }
+    }
+
+    /// Profiles the records of all input files with the `K` representation, taking the solid
+    /// k-mer threshold from the error valley of their spectrum without a `solid` count
+    fn profile<K, F>(
+        &self,
+        inputs: &[PathBuf],
+        solid: Option<f64>,
+        valley: F,
+    ) -> io::Result<Profiles>
+    where
+        K: Kmer,
+        F: Fn(&Spectrum) -> Option<f64>,
+    {
+        let junctions = if self.dedup_junctions {
+            Some(self.read_links(inputs)?.0)
+        } else {
+            None
+        };
+        let records = CheckedRecords::new(read_records(inputs), self, None, junctions);
+        let kmer_counts: KmerCounts<K> = self.count(records)?;
+        let mut spectrum = Spectrum::new(self.bin_width);
+        for count in kmer_counts.values() {
+            spectrum.add(count, 1);
+        }
+        let solid = solid.or_else(|| valley(&spectrum));
+
+        let mut records = Vec::new();
+        let mut counts = Vec::new();
+        for record in read_records(inputs) {
+            let record = record?;
+            counts.clear();
+            counts.extend(
+                generate_encoded_kmers::<K>(record.seq(), self.k, self.canonical, self.trim)
+                    .map(|kmer| kmer_counts.get(&kmer).unwrap_or(0.0)),
+            );
+            records.push(RecordProfile::new(
+                record.id(),
+                record.seq().len(),
+                &mut counts,
+                solid,
+            ));
+        }
+        Ok(Profiles {
+            spectrum,
+            solid,
+            records,
+        })
+    }
+
+    /// Counts the weighted k-mers of the given records, in memory, with the `K` representation
+    pub fn count_kmers<K, I>(&self, records: I) -> io::Result<KmerCounts<K>>
+    where
+        K: Kmer,
+        I: Iterator<Item = io::Result<Record>>,
+    {
+        self.validate()?;
+        if self.k > K::MAX_K {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "k-mer size cannot exceed {} for this representation",
+                    K::MAX_K
+                ),
+            ));
+        }
+        if self.dedup_junctions {
+            return Err(unread_links());
+        }
+        self.count(CheckedRecords::new(records, self, None, None))
+    }
+
+    /// Counts the weighted k-mers of the given records, in memory, with the `K` representation,
+    /// keeping those whose count is in the count range
+    fn count<K, I>(&self, records: I) -> io::Result<KmerCounts<K>>
+    where
+        K: Kmer,
+        I: Iterator<Item = io::Result<Record>>,
+    {
+        let mut kmer_counts = self.count_all(records)?;
+        if self.count_range != bounds(None, None) {
+            kmer_counts.retain(|count| self.count_range.contains(&count));
+        }
+        Ok(kmer_counts)
+    }
+
+    /// Counts the weighted k-mers of the given records, in memory, with the `K` representation
+    fn count_all<K, I>(&self, records: I) -> io::Result<KmerCounts<K>>
+    where
+        K: Kmer,
+        I: Iterator<Item = io::Result<Record>>,
+    {
+        let weight = |record: &Record| self.weight(record);
+        if self.threads > 1 {
+            count_kmers_parallel(
+                records,
+                self.k,
+                self.canonical,
+                self.trim,
+                weight,
+                self.aggregation,
+                self.threads,
+            )
+        } else {
+            count_kmers(
+                records,
+                self.k,
+                self.canonical,
+                self.trim,
+                weight,
+                self.aggregation,
+            )
+        }
+    }
+
+    /// Computes the spectrum, taking the fast path if enabled and `assembly` allows it, dropping
+    /// the k-mers of the junctions that records do not own if given, and updating `stats` if given
+    fn build<I>(
+        &self,
+        records: I,
+        assembly: bool,
+        mut stats: Option<&mut RunStats>,
+        junctions: Option<Vec<(bool, bool)>>,
+    ) -> io::Result<Spectrum>
+    where
+        I: Iterator<Item = io::Result<Record>>,
+    {
+        self.validate()?;
+        if self.dedup_junctions && junctions.is_none() {
+            return Err(unread_links());
+        }
+        let records = CheckedRecords::new(records, self, stats.as_deref_mut(), junctions);
+        // A k-mer occurring once has the same count whatever the aggregation
+        let spectrum = if self.takes_fast_path(assembly) {
+            let weight = |record: &Record| {
+                self.weight(record)
+                    .filter(|count| self.count_range.contains(count))
+            };
+            spectrum_from_lengths(records, self.k, self.trim, weight, self.bin_width)?
+        } else {
+            // Use the narrowest k-mer representation that fits k
+            match self.k {
+                k if k <= u64::MAX_K => self.run::<u64, _>(records)?,
+                k if k <= u128::MAX_K => self.run::<u128, _>(records)?,
+                k if k <= MultiWord::<4>::MAX_K => self.run::<MultiWord<4>, _>(records)?,
+                _ => self.run::<MultiWord<8>, _>(records)?,
+            }
+        };
+
+        if let Some(stats) = stats {
+            stats.distinct_kmers = spectrum.distinct_kmers();
+        }
+        Ok(spectrum)
+    }
+
+    /// Whether spectra are built from sequence lengths, if enabled and `assembly` allows it
+    fn takes_fast_path(&self, assembly: bool) -> bool {
+        match self.fast_path {
+            _ if self.dump.is_some() => false,
+            FastPath::Auto => self.k == self.assembly_k && assembly,
+            FastPath::Always => true,
+            FastPath::Never => false,
+        }
+    }
+
+    /// Counts k-mers with the `K` representation and computes their spectrum
+    fn run<K, I>(&self, records: I) -> io::Result<Spectrum>
+    where
+        K: Kmer,
+        I: Iterator<Item = io::Result<Record>>,
+    {
+        if let Some(max_memory) = self.max_memory {
+            let tmp_dir = self.tmp_dir.clone().unwrap_or_else(std::env::temp_dir);
+            let weight = |record: &Record| self.weight(record);
+            return count_kmers_on_disk::<K, _, _>(
+                records,
+                self.k,
+                self.canonical,
+                self.trim,
+                weight,
+                self.aggregation,
+                max_memory,
+                &tmp_dir,
+                self.bin_width,
+                &self.count_range,
+            );
+        }
+
+        let kmer_counts: KmerCounts<K> = self.count(records)?;
+        if let Some((path, format)) = &self.dump {
+            let mut output = BufWriter::new(File::create(path)?);
+            write_dump(&mut output, &kmer_counts, self.k, self.canonical, *format)?;
+            output.flush()?;
+        }
+        let mut spectrum = Spectrum::new(self.bin_width);
+        for count in kmer_counts.values() {
+            spectrum.add(count, 1);
+        }
+        Ok(spectrum)
+    }
+
+    /// Abundance weighting the k-mers of a record, or `None` to skip it
+    ///
+    /// Records out of the length range or filtered by their header are dropped beforehand by
+    /// [`CheckedRecords`], since the records trimmed at their junctions no longer have their
+    /// original length.
+    fn weight(&self, record: &Record) -> Option<f64> {
+        self.weigh_abundance(self.abundance.abundance(record)).ok()
+    }
+
+    /// Weight of the k-mers of a record with the given abundance, applying the length filter,
+    /// the header filters, the missing abundance policy and the abundance filter, or why the
+    /// record is skipped
+    fn weigh(&self, record: &Record, abundance: HeaderAbundance) -> Result<f64, SkipReason> {
+        if !self.length_range.contains(&record.seq().len()) {
+            return Err(SkipReason::Length);
+        }
+        if !self.header_filters.is_empty() {
+            let header = LoganHeader::from_record(record);
+            if !self
+                .header_filters
+                .iter()
+                .all(|filter| filter.matches(&header))
+            {
+                return Err(SkipReason::HeaderFilter);
+            }
+        }
+        self.weigh_abundance(abundance)
+    }
+
+    /// Weight of the k-mers of a record with the given abundance, applying the missing abundance
+    /// policy and the abundance filter, or why the record is skipped
+    ///
+    /// Unweighted k-mers count 1, but records are filtered and follow the missing abundance
+    /// policy as in the other aggregations.
+    fn weigh_abundance(&self, abundance: HeaderAbundance) -> Result<f64, SkipReason> {
+        let abundance = match (abundance, self.missing_abundance) {
+            (HeaderAbundance::Found(abundance), _) => abundance,
+            (_, MissingAbundance::Value(abundance)) => abundance,
+            (HeaderAbundance::Malformed, _) => return Err(SkipReason::MalformedAbundance),
+            (HeaderAbundance::Missing, _) => return Err(SkipReason::MissingAbundance),
+        };
+        if !self.abundance_range.contains(&abundance) {
+            return Err(SkipReason::AbundanceOutOfBounds);
+        }
+        Ok(match self.aggregation {
+            Aggregation::Unweighted => 1.0,
+            _ => abundance,
+        })
+    }
+
+    /// Applies the error policies, the length filter and the header filters to a record, drops
+    /// the k-mers of the junctions it does not own (`owned` at its start and at its end), and
+    /// accounts for it in `stats`; returns `None` if the record is filtered out
+    fn inspect(
+        &self,
+        record: Record,
+        owned: (bool, bool),
+        stats: Option<&mut RunStats>,
+    ) -> io::Result<Option<Record>> {
+        let abundance = self.abundance.abundance(&record);
+        let error = match abundance {
+            HeaderAbundance::Missing if self.missing_abundance == MissingAbundance::Error => {
+                Some("no abundance")
+            }
+            HeaderAbundance::Malformed
+                if self.strict || self.missing_abundance == MissingAbundance::Error =>
+            {
+                Some("malformed abundance")
+            }
+            _ => None,
+        };
+        if let Some(error) = error {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} in the header of record `{}`", error, record.id()),
+            ));
+        }
+
+        let weight = self.weigh(&record, abundance);
+        // The k-mers of a junction are the ones within the assembly (k-1)-mer it shares
+        let overlap = self.assembly_k.saturating_sub(self.k);
+        let trimmed = match owned {
+            (true, true) => None,
+            _ if overlap == 0 => None,
+            (start, end) => Some(record.trimmed(
+                if start { 0 } else { overlap },
+                if end { 0 } else { overlap },
+            )),
+        };
+        if let Some(stats) = stats {
+            let seq = trimmed.as_ref().unwrap_or(&record).seq();
+            stats.add_record(record.seq().len(), seq, self.k, self.trim, weight);
+        }
+        Ok(match weight {
+            Err(SkipReason::Length | SkipReason::HeaderFilter) => None,
+            _ => Some(trimmed.unwrap_or(record)),
+        })
+    }
+}
+
+/// Error of a spectrum computed from records, whose junctions cannot be deduplicated
+fn unread_links() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "junctions can only be deduplicated when reading input files",
+    )
+}
+
+/// Records checked against the error policies and the length and header filters of a builder,
+/// without the k-mers of the junctions they do not own, and accounted in run statistics
+struct CheckedRecords<'a, I> {
+    records: I,
+    builder: &'a SpectrumBuilder,
+    stats: Option<&'a mut RunStats>,
+    /// Whether each record, in input order, owns the junctions at its start and at its end
+    junctions: Option<std::vec::IntoIter<(bool, bool)>>,
+    /// Whether records need to be inspected at all
+    active: bool,
+}
+
+impl<'a, I> CheckedRecords<'a, I> {
+    fn new(
+        records: I,
+        builder: &'a SpectrumBuilder,
+        stats: Option<&'a mut RunStats>,
+        junctions: Option<Vec<(bool, bool)>>,
+    ) -> Self {
+        let active = stats.is_some()
+            || junctions.is_some()
+            || builder.strict
+            || builder.missing_abundance == MissingAbundance::Error
+            || builder.length_range != (0..=usize::MAX)
+            || !builder.header_filters.is_empty();
+        CheckedRecords {
+            records,
+            builder,
+            stats,
+            junctions: junctions.map(Vec::into_iter),
+            active,
+        }
+    }
+}
+
+impl<I: Iterator<Item = io::Result<Record>>> Iterator for CheckedRecords<'_, I> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let result = self.records.next()?;
+            if !self.active {
+                return Some(result);
+            }
+            let owned = self
+                .junctions
+                .as_mut()
+                .and_then(Iterator::next)
+                .unwrap_or((true, true));
+            let inspected = result.and_then(|record| {
+                self.builder
+                    .inspect(record, owned, self.stats.as_deref_mut())
+            });
+            match inspected {
+                Ok(None) => continue,
+                inspected => return inspected.transpose(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bio::io::fasta;
+
+    /// A record with an abundance, one without and one with a malformed abundance
+    fn records() -> impl Iterator<Item = io::Result<Record>> {
+        [
+            ("a", Some("ka:f:3"), b"ACGTAC".as_slice()),
+            ("b", None, b"GGGG".as_slice()),
+            ("c", Some("ka:f:abc"), b"TTTA".as_slice()),
+        ]
+        .into_iter()
+        .map(|(id, desc, seq)| Ok(Record::Fasta(fasta::Record::with_attrs(id, desc, seq))))
+    }
+
+    #[test]
+    fn unweighted_records_follow_the_abundance_policies() {
+        let builder = SpectrumBuilder::new(3)
+            .aggregation(Aggregation::Unweighted)
+            .fast_path(FastPath::Never);
+
+        let (spectrum, stats) = builder.build_from_records_with_stats(records()).unwrap();
+        assert_eq!(spectrum.bins().collect::<Vec<_>>(), [(1, 4)]);
+        assert_eq!(
+            (
+                stats.records,
+                stats.missing_abundance,
+                stats.malformed_abundance
+            ),
+            (3, 1, 1)
+        );
+        assert_eq!((stats.kmers, stats.weighted_kmers), (4, 4.0));
+
+        let builder = builder.missing_abundance(MissingAbundance::Value(5.0));
+        let (spectrum, stats) = builder.build_from_records_with_stats(records()).unwrap();
+        assert_eq!(spectrum.bins().collect::<Vec<_>>(), [(1, 6), (2, 1)]);
+        assert_eq!((stats.kmers, stats.weighted_kmers), (8, 8.0));
+
+        let error = builder.clone().strict(true).build_from_records(records());
+        assert_eq!(error.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let error = builder
+            .missing_abundance(MissingAbundance::Error)
+            .build_from_records(records());
+        assert_eq!(error.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    /// Random records of the given abundances, whose 21-mers occur once as in an assembly
+    fn contigs(abundances: &[f64]) -> Vec<Record> {
+        let mut state = 0x5851_f42d_4c95_7f2d_u64;
+        let mut next = move || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            state >> 33
+        };
+        abundances
+            .iter()
+            .enumerate()
+            .map(|(i, abundance)| {
+                let seq: Vec<u8> = (0..30 + next() % 70)
+                    .map(|_| b"ACGT"[next() as usize % 4])
+                    .collect();
+                let desc = format!("ka:f:{}", abundance);
+                Record::Fasta(fasta::Record::with_attrs(&i.to_string(), Some(&desc), &seq))
+            })
+            .collect()
+    }
+
+    fn kmers(record: &Record, k: usize) -> u64 {
+        (record.seq().len() + 1 - k) as u64
+    }
+
+    #[test]
+    fn skips_records_out_of_the_abundance_range() {
+        let contigs = contigs(&[1.0, 2.0, 3.0, 4.0, 8.0]);
+        let builder = SpectrumBuilder::new(21)
+            .fast_path(FastPath::Never)
+            .abundance_range(Some(2.0), Some(4.0));
+        let (spectrum, stats) = builder
+            .build_from_records_with_stats(contigs.iter().cloned().map(Ok))
+            .unwrap();
+        let expected: Vec<(u64, u64)> = (1..4)
+            .map(|i| (i as u64 + 1, kmers(&contigs[i], 21)))
+            .collect();
+        assert_eq!(spectrum.bins().collect::<Vec<_>>(), expected);
+        assert_eq!(stats.filtered_abundance, 2);
+
+        // The abundance given to records without one is filtered as well
+        let bare = Record::Fasta(fasta::Record::with_attrs("bare", None, contigs[0].seq()));
+        for (missing, bin) in [(3.0, Some(3)), (10.0, None)] {
+            let spectrum = builder
+                .clone()
+                .missing_abundance(MissingAbundance::Value(missing))
+                .build_from_records([Ok(bare.clone())].into_iter())
+                .unwrap();
+            let expected: Vec<(u64, u64)> =
+                bin.map(|bin| (bin, kmers(&bare, 21))).into_iter().collect();
+            assert_eq!(spectrum.bins().collect::<Vec<_>>(), expected);
+        }
+    }
+
+    #[test]
+    fn drops_kmers_out_of_the_count_range() {
+        // ACG and CGT occur in both records, with a count of 3
+        let records = || {
+            [
+                ("a", "ka:f:1", b"ACGTAC".as_slice()),
+                ("b", "ka:f:2", b"ACGT"),
+            ]
+            .into_iter()
+            .map(|(id, desc, seq)| {
+                Ok(Record::Fasta(fasta::Record::with_attrs(
+                    id,
+                    Some(desc),
+                    seq,
+                )))
+            })
+        };
+        let builder = SpectrumBuilder::new(3).fast_path(FastPath::Never);
+        let spectrum = builder.build_from_records(records()).unwrap();
+        assert_eq!(spectrum.bins().collect::<Vec<_>>(), [(1, 2), (3, 2)]);
+
+        let builder = builder.count_range(Some(2.0), None);
+        let spectrum = builder.build_from_records(records()).unwrap();
+        assert_eq!(spectrum.bins().collect::<Vec<_>>(), [(3, 2)]);
+        let counts: KmerCounts<u64> = builder.count_kmers(records()).unwrap();
+        assert_eq!(counts.len(), 2);
+
+        let builder = builder.count_range(None, Some(2.0));
+        let spectrum = builder.build_from_records(records()).unwrap();
+        assert_eq!(spectrum.bins().collect::<Vec<_>>(), [(1, 2)]);
+    }
+
+    #[test]
+    fn skips_records_out_of_the_length_range_and_trims_the_others() {
+        let contigs = contigs(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]);
+        let builder = SpectrumBuilder::new(21)
+            .fast_path(FastPath::Never)
+            .length_range(Some(50), Some(80))
+            .trim(5);
+        let (spectrum, stats) = builder
+            .build_from_records_with_stats(contigs.iter().cloned().map(Ok))
+            .unwrap();
+        let kept: Vec<(u64, u64)> = contigs
+            .iter()
+            .enumerate()
+            .filter(|(_, contig)| (50..=80).contains(&contig.seq().len()))
+            .map(|(i, contig)| (i as u64 + 1, kmers(contig, 21) - 10))
+            .collect();
+        assert!(!kept.is_empty() && kept.len() < contigs.len());
+        assert_eq!(spectrum.bins().collect::<Vec<_>>(), kept);
+        assert_eq!(stats.filtered_length, (contigs.len() - kept.len()) as u64);
+        assert_eq!(
+            stats.kmers,
+            kept.iter().map(|(_, kmers)| kmers).sum::<u64>()
+        );
+
+        // Records not longer than twice the trimmed bases have no k-mer left
+        let spectrum = builder
+            .trim(40)
+            .build_from_records(contigs.iter().cloned().map(Ok))
+            .unwrap();
+        assert!(spectrum.is_empty());
+    }
+
+    #[test]
+    fn fast_path_matches_hash_counting_with_filters() {
+        let abundances: Vec<f64> = (0..200).map(|i| 1.0 + (i % 13) as f64 / 4.0).collect();
+        let contigs = contigs(&abundances);
+        let ranges = [
+            ((None, None), (None, None)),
+            ((Some(1.5), Some(3.5)), (None, None)),
+            ((None, None), (Some(2.0), Some(3.0))),
+            ((Some(1.5), None), (None, Some(2.5))),
+        ];
+        for aggregation in [Aggregation::Sum, Aggregation::Unweighted] {
+            for (bin_width, trim, (min_length, max_length)) in [
+                (None, 0, (None, None)),
+                (Some(0.5), 0, (None, None)),
+                (None, 4, (Some(40), Some(90))),
+            ] {
+                for ((min_abundance, max_abundance), (min_count, max_count)) in ranges {
+                    let builder = SpectrumBuilder::new(21)
+                        .aggregation(aggregation)
+                        .bin_width(bin_width)
+                        .trim(trim)
+                        .length_range(min_length, max_length)
+                        .abundance_range(min_abundance, max_abundance)
+                        .count_range(min_count, max_count);
+                    let [fast, hashed] = [FastPath::Always, FastPath::Never].map(|fast_path| {
+                        builder
+                            .clone()
+                            .fast_path(fast_path)
+                            .build_from_records(contigs.iter().cloned().map(Ok))
+                            .unwrap()
+                    });
+                    assert_eq!(
+                        fast, hashed,
+                        "{:?} {:?} {:?} trim {}",
+                        aggregation, min_abundance, min_count, trim
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn profiles_records_from_the_counts_of_all_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("reads.fa");
+        std::fs::write(&path, ">a ka:f:1\nAAAAC\n>b ka:f:1\nAAAG\n").unwrap();
+        let builder = SpectrumBuilder::new(3).aggregation(Aggregation::Unweighted);
+        let profile = |builder: &SpectrumBuilder| {
+            builder.profile_paths(&[&path], Some(2.0), Smoothing::Average, 3, 0.05)
+        };
+
+        let profiles = profile(&builder).unwrap();
+        assert_eq!(
+            profiles.spectrum.bins().collect::<Vec<_>>(),
+            [(1, 2), (3, 1)]
+        );
+        let summary: Vec<_> = profiles
+            .records
+            .iter()
+            .map(|record| (record.min, record.median, record.max, record.solid_fraction))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (Some(1.0), Some(3.0), Some(3.0), Some(2.0 / 3.0)),
+                (Some(1.0), Some(2.0), Some(3.0), Some(0.5)),
+            ]
+        );
+
+        let error = profile(&builder.max_memory(Some(1 << 20))).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn profiles_junction_kmers_counted_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("unitigs.fa");
+        std::fs::write(
+            &path,
+            ">u_1 ka:f:1 L:+:2:+\nAAACC\n>u_2 ka:f:1 L:-:1:-\nACCGG\n",
+        )
+        .unwrap();
+        let builder = SpectrumBuilder::new(3)
+            .canonical(true)
+            .assembly_k(4)
+            .aggregation(Aggregation::Unweighted);
+        let max = |builder: SpectrumBuilder| {
+            let profiles = builder
+                .profile_paths(&[&path], None, Smoothing::Average, 3, 0.05)
+                .unwrap();
+            profiles.records[0].max
+        };
+        // The junction k-mer `ACC` only belongs to the unitig of smallest id
+        assert_eq!(max(builder.clone()), Some(2.0));
+        assert_eq!(max(builder.dedup_junctions(true)), Some(1.0));
+    }
+}
@@ -0,0 +1,131 @@
+//! Statistics of the records and k-mers seen while computing a spectrum
+
+use std::fmt;
+
+use crate::graph::GraphStats;
+use crate::kmer::{count_valid_kmers, trim_sequence};
+
+/// Why the k-mers of a record are not counted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkipReason {
+    /// The header has no abundance
+    MissingAbundance,
+    /// The abundance field of the header is not a number
+    MalformedAbundance,
+    /// The abundance is out of the abundance filter bounds
+    AbundanceOutOfBounds,
+    /// The sequence length is out of the length filter bounds
+    Length,
+    /// The header does not satisfy a header filter
+    HeaderFilter,
+}
+
+/// Counters of the records and k-mers seen while computing a spectrum
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RunStats {
+    /// Records read from the inputs
+    pub records: u64,
+    /// Records skipped because their header has no abundance
+    pub missing_abundance: u64,
+    /// Records skipped because the abundance field of their header is not a number
+    pub malformed_abundance: u64,
+    /// Records skipped because their abundance is out of the abundance filter bounds
+    pub filtered_abundance: u64,
+    /// Records skipped because their length is out of the length filter bounds
+    pub filtered_length: u64,
+    /// Records skipped because their header does not satisfy a header filter
+    pub filtered_header: u64,
+    /// Bases of all records read
+    pub bases: u64,
+    /// k-mers counted, from the records that were not skipped
+    pub kmers: u64,
+    /// k-mer windows of the counted records (after trimming) skipped for containing a base other
+    /// than ACGT
+    pub invalid_kmers: u64,
+    /// Distinct k-mers of the spectrum
+    pub distinct_kmers: u64,
+    /// Sum of the abundances of the counted k-mers
+    pub weighted_kmers: f64,
+    /// Statistics of the graph of the unitigs linked in the headers, if it was read
+    pub graph: Option<GraphStats>,
+}
+
+impl RunStats {
+    /// Accounts for a record of `length` bases whose k-mers, those of `seq` (its sequence, without
+    /// the junctions owned by other unitigs) trimmed by `trim` bases at both ends, are weighted by
+    /// `weight` or skipped for the given reason
+    pub fn add_record(
+        &mut self,
+        length: usize,
+        seq: &[u8],
+        k: usize,
+        trim: usize,
+        weight: Result<f64, SkipReason>,
+    ) {
+        self.records += 1;
+        self.bases += length as u64;
+        match weight {
+            Ok(weight) => {
+                let windows = (trim_sequence(seq, trim).len() + 1).saturating_sub(k) as u64;
+                let kmers = count_valid_kmers(seq, k, trim);
+                self.kmers += kmers;
+                self.invalid_kmers += windows - kmers;
+                self.weighted_kmers += weight * kmers as f64;
+            }
+            Err(SkipReason::MissingAbundance) => self.missing_abundance += 1,
+            Err(SkipReason::MalformedAbundance) => self.malformed_abundance += 1,
+            Err(SkipReason::AbundanceOutOfBounds) => self.filtered_abundance += 1,
+            Err(SkipReason::Length) => self.filtered_length += 1,
+            Err(SkipReason::HeaderFilter) => self.filtered_header += 1,
+        }
+    }
+
+    /// Records skipped, whatever the reason
+    pub fn skipped_records(&self) -> u64 {
+        self.missing_abundance
+            + self.malformed_abundance
+            + self.filtered_abundance
+            + self.filtered_length
+            + self.filtered_header
+    }
+}
+
+impl fmt::Display for RunStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Records read: {}", self.records)?;
+        writeln!(
+            f,
+            "Records skipped without abundance: {}",
+            self.missing_abundance
+        )?;
+        writeln!(
+            f,
+            "Records skipped with a malformed abundance: {}",
+            self.malformed_abundance
+        )?;
+        writeln!(
+            f,
+            "Records skipped for an abundance out of bounds: {}",
+            self.filtered_abundance
+        )?;
+        writeln!(
+            f,
+            "Records skipped for a length out of bounds: {}",
+            self.filtered_length
+        )?;
+        writeln!(
+            f,
+            "Records skipped by a header filter: {}",
+            self.filtered_header
+        )?;
+        writeln!(f, "Bases: {}", self.bases)?;
+        writeln!(f, "k-mers counted: {}", self.kmers)?;
+        writeln!(
+            f,
+            "k-mers skipped for non-ACGT bases: {}",
+            self.invalid_kmers
+        )?;
+        writeln!(f, "Distinct k-mers: {}", self.distinct_kmers)?;
+        write!(f, "Total weighted k-mers: {}", self.weighted_kmers)
+    }
+}